@@ -0,0 +1,9 @@
+#version 130
+
+uniform vec3 color;
+
+out vec4 finalColor;
+
+void main() {
+    finalColor = vec4(color, 1);
+}
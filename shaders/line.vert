@@ -0,0 +1,7 @@
+#version 130
+
+in vec2 pos;
+
+void main() {
+    gl_Position = vec4(pos, 1, 1);
+}
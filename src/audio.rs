@@ -0,0 +1,158 @@
+use std::thread;
+use std::time::Duration;
+
+use sdl2::AudioSubsystem;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+
+use rgb::emulator::SAMPLE_RATE;
+
+const DEVICE_SAMPLE_RATE: i32 = 44100;
+// How many (stereo) frames of audio to keep queued up when syncing emulation to audio
+const TARGET_QUEUED_FRAMES: u32 = 2048;
+// Samples are dropped instead of queued past this many waiting frames
+const MAX_QUEUED_FRAMES: u32 = 8192;
+// The most the playback rate is nudged by to keep the queue near TARGET_QUEUED_FRAMES
+const MAX_RATE_ADJUSTMENT: f64 = 0.01;
+const BYTES_PER_FRAME: u32 = 2*4;
+
+// Converts the emulator's stereo samples to the audio device's sample rate by linear interpolation
+pub struct Resampler {
+	// Position of the next output frame relative to the start of the next chunk of input
+	// (may be negative, in which case it falls between last and the first frame of that chunk)
+	pos:	f64,
+	last:	(f32, f32)
+}
+
+impl Resampler {
+	pub fn new() -> Resampler {
+		Resampler{pos: 0.0, last: (0.0, 0.0)}
+	}
+	// ratio is the number of input frames consumed per output frame
+	pub fn resample(&mut self, input: &[f32], ratio: f64, output: &mut Vec<f32>) {
+		let frames = input.len()/2;
+		if frames == 0 {
+			return;
+		}
+		let frame = |i: isize, last: (f32, f32)| {
+			if i < 0 {last} else {(input[2*i as usize], input[2*i as usize + 1])}
+		};
+
+		while self.pos < (frames - 1) as f64 {
+			let i = self.pos.floor() as isize;
+			let frac = (self.pos - i as f64) as f32;
+			let (a, b) = (frame(i, self.last), frame(i + 1, self.last));
+			output.push(a.0 + (b.0 - a.0)*frac);
+			output.push(a.1 + (b.1 - a.1)*frac);
+			self.pos += ratio;
+		}
+		self.pos -= frames as f64;
+		self.last = frame(frames as isize - 1, self.last);
+	}
+}
+
+// How much faster than normal to consume samples with this many frames queued up, so the queue
+// drifts back towards its target (instead of overflowing or running dry) when emulation and the
+// audio device disagree slightly about how fast time passes
+fn rate_adjustment(queued_frames: u32) -> f64 {
+	let error = (queued_frames as f64 - TARGET_QUEUED_FRAMES as f64)/TARGET_QUEUED_FRAMES as f64;
+	1.0 + MAX_RATE_ADJUSTMENT*error.max(-1.0).min(1.0)
+}
+
+pub struct AudioPlayer {
+	queue:		AudioQueue<f32>,
+	resampler:	Resampler,
+	buffer:		Vec<f32>
+}
+
+impl AudioPlayer {
+	pub fn new(audio: &AudioSubsystem) -> Result<AudioPlayer, String> {
+		let desired = AudioSpecDesired {
+			freq: Some(DEVICE_SAMPLE_RATE),
+			channels: Some(2),
+			samples: Some(1024)
+		};
+		let queue = audio.open_queue::<f32>(None, &desired)?;
+		println!("Playing audio at {} Hz", queue.spec().freq);
+		queue.resume();
+
+		Ok(AudioPlayer {
+			queue: queue,
+			resampler: Resampler::new(),
+			buffer: Vec::new()
+		})
+	}
+	fn queued_frames(&self) -> u32 {
+		self.queue.size()/BYTES_PER_FRAME
+	}
+	// speed is how many times faster than normal the emulator is currently running
+	pub fn play(&mut self, samples: &[f32], speed: u64) {
+		let queued = self.queued_frames();
+		let ratio = SAMPLE_RATE as f64 * speed as f64/self.queue.spec().freq as f64 * rate_adjustment(queued);
+		self.buffer.clear();
+		self.resampler.resample(samples, ratio, &mut self.buffer);
+
+		// Only whatever doesn't fit is dropped
+		let room = 2*MAX_QUEUED_FRAMES.saturating_sub(queued) as usize;
+		let len = self.buffer.len().min(room);
+		self.queue.queue(&self.buffer[..len]);
+	}
+	// Blocks until the device has used up enough of the queued audio that it is time for another frame
+	pub fn wait_for_space(&self) {
+		while self.queued_frames() > TARGET_QUEUED_FRAMES {
+			thread::sleep(Duration::from_millis(1));
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	// Stereo samples with the same value in both channels
+	fn stereo(mono: &[f32]) -> Vec<f32> {
+		mono.iter().flat_map(|&s| vec![s, s]).collect()
+	}
+
+	#[test]
+	fn test_rate_adjustment() {
+		assert_eq!(rate_adjustment(TARGET_QUEUED_FRAMES), 1.0);
+		assert!(rate_adjustment(TARGET_QUEUED_FRAMES + 100) > 1.0);
+		assert!(rate_adjustment(TARGET_QUEUED_FRAMES - 100) < 1.0);
+		assert_eq!(rate_adjustment(0), 1.0 - MAX_RATE_ADJUSTMENT);
+		assert_eq!(rate_adjustment(MAX_QUEUED_FRAMES), 1.0 + MAX_RATE_ADJUSTMENT);
+	}
+	#[test]
+	fn test_same_rate() {
+		// The last frame of each chunk is held back until the next chunk arrives
+		let mut resampler = Resampler::new();
+		let mut output = Vec::new();
+		resampler.resample(&stereo(&[1.0, 2.0, 3.0, 4.0]), 1.0, &mut output);
+		assert_eq!(output, stereo(&[1.0, 2.0, 3.0]));
+		resampler.resample(&stereo(&[5.0, 6.0]), 1.0, &mut output);
+		assert_eq!(output, stereo(&[1.0, 2.0, 3.0, 4.0, 5.0]));
+	}
+	#[test]
+	fn test_upsample() {
+		let mut resampler = Resampler::new();
+		let mut output = Vec::new();
+		resampler.resample(&stereo(&[0.0, 1.0]), 0.5, &mut output);
+		resampler.resample(&stereo(&[3.0]), 0.5, &mut output);
+		assert_eq!(output, stereo(&[0.0, 0.5, 1.0, 2.0]));
+	}
+	#[test]
+	fn test_downsample() {
+		// Over many chunks, the output is the input's length divided by the ratio
+		let mut resampler = Resampler::new();
+		let mut output = Vec::new();
+		let input = stereo(&(0..100).map(|i| i as f32).collect::<Vec<_>>());
+		for chunk in input.chunks(14) {
+			resampler.resample(chunk, 2.5, &mut output);
+		}
+		assert_eq!(output, stereo(&(0..40).map(|i| 2.5*i as f32).collect::<Vec<_>>()));
+
+		// Each channel is resampled separately
+		let mut output = Vec::new();
+		Resampler::new().resample(&[1.0, -1.0, 3.0, -3.0, 5.0, -5.0], 1.5, &mut output);
+		assert_eq!(output, vec![1.0, -1.0, 4.0, -4.0]);
+	}
+}
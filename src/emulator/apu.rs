@@ -0,0 +1,436 @@
+use std::io;
+use std::io::{Write, Read};
+
+use emulator::channels::*;
+use emulator::serialize::*;
+
+// Samples are produced at a fixed rate; frontends are expected to resample as needed
+pub const CYCLES_PER_SAMPLE: i32 = 64;
+pub const SAMPLE_RATE: u32 = 4194304/CYCLES_PER_SAMPLE as u32; //65536 Hz
+
+// The frame sequencer runs at 512 Hz (4194304/512 = 8192 cycles)
+const CYCLES_PER_SEQUENCER_STEP: i32 = 8192;
+
+// Don't let samples pile up forever if nobody is reading them (one second of stereo audio)
+const MAX_BUFFERED_SAMPLES: usize = 2*SAMPLE_RATE as usize;
+
+// How many of each channel's most recent samples are kept around for visualization
+pub const SCOPE_LENGTH: usize = 512;
+
+// Bits that always read back as 1 for 0xFF10-0xFF26
+const READ_MASKS: [u8; 0x17] = [
+	0x80, 0x3F, 0x00, 0xFF, 0xBF, //NR10-NR14
+	0xFF, 0x3F, 0x00, 0xFF, 0xBF, //NR20-NR24
+	0x7F, 0xFF, 0x9F, 0xFF, 0xBF, //NR30-NR34
+	0xFF, 0xFF, 0x00, 0x00, 0xBF, //NR40-NR44
+	0x00, 0x00, 0x70			  //NR50-NR52
+];
+
+// Audio Processing Unit (handles 0xFF10-0xFF3F)
+pub struct Apu {
+	square1:		SquareChannel,
+	square2:		SquareChannel,
+	wave:			WaveChannel,
+	noise:			NoiseChannel,
+	regs:			[u8; 0x17], //Last values written to 0xFF10-0xFF26
+	powered:		bool,
+	sequencer_step:	u8,
+	sequencer_timer:i32,
+	sample_timer:	i32,
+	// High-pass filter state for removing DC offset
+	capacitors:		(f32, f32),
+	// Interleaved stereo samples (left, right, left, right, ...) in the range [-1, 1]
+	samples:		Vec<f32>,
+	// Muted channels are still emulated, just left out of the mix
+	muted:			[bool; 4],
+	// Ring buffers of each channel's DAC output (before muting and panning)
+	scope:			[[f32; SCOPE_LENGTH]; 4],
+	scope_pos:		usize
+}
+
+impl Apu {
+	pub fn new() -> Apu {
+		Apu {
+			square1: SquareChannel::new(),
+			square2: SquareChannel::new(),
+			wave: WaveChannel::new(),
+			noise: NoiseChannel::new(),
+			regs: [0; 0x17],
+			powered: false,
+			sequencer_step: 0,
+			sequencer_timer: CYCLES_PER_SEQUENCER_STEP,
+			sample_timer: CYCLES_PER_SAMPLE,
+			capacitors: (0.0, 0.0),
+			samples: Vec::new(),
+			muted: [false; 4],
+			scope: [[0.0; SCOPE_LENGTH]; 4],
+			scope_pos: 0
+		}
+	}
+	pub fn rb(&self, address: u16) -> u8 {
+		match address {
+			0xFF10...0xFF25 => self.regs[address as usize - 0xFF10] | READ_MASKS[address as usize - 0xFF10],
+			0xFF26 => {
+				READ_MASKS[0x16] | ((self.powered as u8) << 7) |
+				((self.noise.enabled as u8) << 3) | ((self.wave.enabled as u8) << 2) |
+				((self.square2.enabled as u8) << 1) | self.square1.enabled as u8
+			},
+			0xFF30...0xFF3F => self.wave.wave_ram[address as usize - 0xFF30],
+			_ => 0xFF
+		}
+	}
+	pub fn wb(&mut self, address: u16, val: u8) {
+		match address {
+			0xFF26 => self.set_power(val & 0x80 > 0),
+			0xFF30...0xFF3F => self.wave.wave_ram[address as usize - 0xFF30] = val,
+			0xFF10...0xFF25 if self.powered => {
+				self.regs[address as usize - 0xFF10] = val;
+				self.write_register(address, val);
+			},
+			_ => {}
+		}
+	}
+	fn write_register(&mut self, address: u16, val: u8) {
+		match address {
+			0xFF10 => self.square1.sweep.write(val),
+			0xFF11 => {
+				self.square1.duty = val >> 6;
+				self.square1.length.load(val & 0x3F);
+			},
+			0xFF12 => {
+				self.square1.envelope.write(val);
+				if !self.square1.envelope.dac_enabled() {
+					self.square1.enabled = false;
+				}
+			},
+			0xFF13 => self.square1.frequency = (self.square1.frequency & 0x700) | val as u16,
+			0xFF14 => {
+				self.square1.frequency = (self.square1.frequency & 0xFF) | ((val as u16 & 7) << 8);
+				self.square1.length.enabled = val & 0x40 > 0;
+				if val & 0x80 > 0 {
+					self.square1.trigger(true);
+				}
+			},
+			0xFF16 => {
+				self.square2.duty = val >> 6;
+				self.square2.length.load(val & 0x3F);
+			},
+			0xFF17 => {
+				self.square2.envelope.write(val);
+				if !self.square2.envelope.dac_enabled() {
+					self.square2.enabled = false;
+				}
+			},
+			0xFF18 => self.square2.frequency = (self.square2.frequency & 0x700) | val as u16,
+			0xFF19 => {
+				self.square2.frequency = (self.square2.frequency & 0xFF) | ((val as u16 & 7) << 8);
+				self.square2.length.enabled = val & 0x40 > 0;
+				if val & 0x80 > 0 {
+					self.square2.trigger(false);
+				}
+			},
+			0xFF1A => {
+				self.wave.dac_enabled = val & 0x80 > 0;
+				if !self.wave.dac_enabled {
+					self.wave.enabled = false;
+				}
+			},
+			0xFF1B => self.wave.length.load(val),
+			0xFF1C => self.wave.volume_code = (val >> 5) & 3,
+			0xFF1D => self.wave.frequency = (self.wave.frequency & 0x700) | val as u16,
+			0xFF1E => {
+				self.wave.frequency = (self.wave.frequency & 0xFF) | ((val as u16 & 7) << 8);
+				self.wave.length.enabled = val & 0x40 > 0;
+				if val & 0x80 > 0 {
+					self.wave.trigger();
+				}
+			},
+			0xFF20 => self.noise.length.load(val & 0x3F),
+			0xFF21 => {
+				self.noise.envelope.write(val);
+				if !self.noise.envelope.dac_enabled() {
+					self.noise.enabled = false;
+				}
+			},
+			0xFF22 => self.noise.write_polynomial(val),
+			0xFF23 => {
+				self.noise.length.enabled = val & 0x40 > 0;
+				if val & 0x80 > 0 {
+					self.noise.trigger();
+				}
+			},
+			_ => {}
+		}
+	}
+	fn set_power(&mut self, on: bool) {
+		if self.powered && !on {
+			// Turning the APU off clears every register (but not wave RAM)
+			let wave_ram = self.wave.wave_ram;
+			self.square1 = SquareChannel::new();
+			self.square2 = SquareChannel::new();
+			self.wave = WaveChannel::new();
+			self.wave.wave_ram = wave_ram;
+			self.noise = NoiseChannel::new();
+			self.regs = [0; 0x17];
+		} else if !self.powered && on {
+			self.sequencer_step = 0;
+		}
+		self.powered = on;
+	}
+	// Saves everything except buffered samples and debugging state (muted channels and scopes)
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		self.square1.save_state(out)?;
+		self.square2.save_state(out)?;
+		self.wave.save_state(out)?;
+		self.noise.save_state(out)?;
+		write_bytes(out, &self.regs)?;
+		write_bool(out, self.powered)?;
+		write_u8(out, self.sequencer_step)?;
+		write_i32(out, self.sequencer_timer)?;
+		write_i32(out, self.sample_timer)?;
+		write_u32(out, self.capacitors.0.to_bits())?;
+		write_u32(out, self.capacitors.1.to_bits())
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.square1.load_state(inp)?;
+		self.square2.load_state(inp)?;
+		self.wave.load_state(inp)?;
+		self.noise.load_state(inp)?;
+		read_bytes(inp, &mut self.regs)?;
+		self.powered = read_bool(inp)?;
+		self.sequencer_step = read_u8(inp)? % 8;
+		self.sequencer_timer = read_i32(inp)?;
+		self.sample_timer = read_i32(inp)?;
+		self.capacitors.0 = f32::from_bits(read_u32(inp)?);
+		self.capacitors.1 = f32::from_bits(read_u32(inp)?);
+		self.samples.clear();
+		Ok(())
+	}
+	pub fn is_powered(&self) -> bool {
+		self.powered
+	}
+	pub fn step(&mut self, cycles: i16) {
+		let cycles = cycles as i32;
+		if self.powered {
+			self.square1.step(cycles);
+			self.square2.step(cycles);
+			self.wave.step(cycles);
+			self.noise.step(cycles);
+
+			self.sequencer_timer -= cycles;
+			while self.sequencer_timer <= 0 {
+				self.sequencer_timer += CYCLES_PER_SEQUENCER_STEP;
+				self.clock_sequencer();
+			}
+		}
+
+		self.sample_timer -= cycles;
+		while self.sample_timer <= 0 {
+			self.sample_timer += CYCLES_PER_SAMPLE;
+			self.record_scope();
+			let (left, right) = self.mix();
+			if self.samples.len() >= MAX_BUFFERED_SAMPLES {
+				self.samples.drain(..MAX_BUFFERED_SAMPLES/2);
+			}
+			self.samples.push(left);
+			self.samples.push(right);
+		}
+	}
+	fn clock_sequencer(&mut self) {
+		// Length counters on even steps, sweep on steps 2 and 6 and envelopes on step 7
+		if self.sequencer_step%2 == 0 {
+			if self.square1.length.clock() {self.square1.enabled = false;}
+			if self.square2.length.clock() {self.square2.enabled = false;}
+			if self.wave.length.clock() {self.wave.enabled = false;}
+			if self.noise.length.clock() {self.noise.enabled = false;}
+		}
+		if self.sequencer_step == 2 || self.sequencer_step == 6 {
+			self.square1.clock_sweep();
+		}
+		if self.sequencer_step == 7 {
+			self.square1.envelope.clock();
+			self.square2.envelope.clock();
+			self.noise.envelope.clock();
+		}
+		self.sequencer_step = (self.sequencer_step + 1)%8;
+	}
+	// Digital output (0-15) of each channel
+	pub fn channel_outputs(&self) -> [u8; 4] {
+		[self.square1.output(), self.square2.output(), self.wave.output(), self.noise.output()]
+	}
+	fn dac_outputs(&self) -> [f32; 4] {
+		let dacs = [
+			self.square1.envelope.dac_enabled(),
+			self.square2.envelope.dac_enabled(),
+			self.wave.dac_enabled,
+			self.noise.envelope.dac_enabled()
+		];
+		let digital = self.channel_outputs();
+		let mut analog = [0.0; 4];
+		for i in 0..4 {
+			if dacs[i] {
+				analog[i] = digital[i] as f32/7.5 - 1.0;
+			}
+		}
+		analog
+	}
+	fn record_scope(&mut self) {
+		let analog = if self.powered {self.dac_outputs()} else {[0.0; 4]};
+		for i in 0..4 {
+			self.scope[i][self.scope_pos] = analog[i];
+		}
+		self.scope_pos = (self.scope_pos + 1)%SCOPE_LENGTH;
+	}
+	// The last SCOPE_LENGTH outputs (in [-1, 1]) of a channel (0-3), oldest first
+	pub fn channel_scope(&self, channel: usize) -> Vec<f32> {
+		let scope = &self.scope[channel];
+		scope[self.scope_pos..].iter().chain(scope[..self.scope_pos].iter()).cloned().collect()
+	}
+	pub fn is_channel_muted(&self, channel: usize) -> bool {
+		self.muted[channel]
+	}
+	pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+		self.muted[channel] = muted;
+	}
+	// Mutes every channel except the given one
+	// Soloing a channel that is already the only one playing unmutes everything instead
+	pub fn solo_channel(&mut self, channel: usize) {
+		let mut solo = [true; 4];
+		solo[channel] = false;
+		self.muted = if self.muted == solo {[false; 4]} else {solo};
+	}
+	fn mix(&mut self) -> (f32, f32) {
+		if !self.powered {
+			return (0.0, 0.0);
+		}
+
+		let analog = self.dac_outputs();
+		let (nr50, nr51) = (self.regs[0x14], self.regs[0x15]);
+		let (mut left, mut right) = (0.0, 0.0);
+		for i in (0..4).filter(|&i| !self.muted[i]) {
+			if nr51 & (1 << (i + 4)) > 0 {
+				left += analog[i];
+			}
+			if nr51 & (1 << i) > 0 {
+				right += analog[i];
+			}
+		}
+		// Each side's volume goes from 1/8 to 8/8, and there are 4 channels to fit in [-1, 1]
+		left *= ((nr50 >> 4) & 7) as f32 + 1.0;
+		right *= (nr50 & 7) as f32 + 1.0;
+		let (left, right) = (left/32.0, right/32.0);
+
+		(self.high_pass(left, true), self.high_pass(right, false))
+	}
+	fn high_pass(&mut self, input: f32, left: bool) -> f32 {
+		// 0.999958^CYCLES_PER_SAMPLE
+		const CHARGE_FACTOR: f32 = 0.997315;
+		let capacitor = if left {&mut self.capacitors.0} else {&mut self.capacitors.1};
+		let output = input - *capacitor;
+		*capacitor = input - output*CHARGE_FACTOR;
+		output
+	}
+	// Removes and returns all samples produced so far
+	pub fn take_samples(&mut self) -> Vec<f32> {
+		::std::mem::replace(&mut self.samples, Vec::new())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_corrupt_sweep_state() {
+		// Out of range values are masked like the register write would, instead of overflowing later
+		let mut sweep = Sweep::new();
+		sweep.load_state(&mut &[0xFF, 0, 0xFF, 1, 0xFF, 0xFF, 1][..]).unwrap();
+		sweep.clock();
+		sweep.trigger(0x400);
+	}
+
+	#[test]
+	fn test_power_and_status() {
+		let mut apu = Apu::new();
+		apu.wb(0xFF12, 0xF0);
+		assert_eq!(apu.rb(0xFF12), 0x00);
+		apu.wb(0xFF26, 0x80);
+		apu.wb(0xFF12, 0xF0);
+		apu.wb(0xFF14, 0x80);
+		assert_eq!(apu.rb(0xFF26), 0xF1);
+		assert_eq!(apu.rb(0xFF14), 0xBF);
+
+		apu.wb(0xFF30, 0x12);
+		apu.wb(0xFF26, 0x00);
+		assert_eq!(apu.rb(0xFF26), 0x70);
+		assert_eq!(apu.rb(0xFF12), 0x00);
+		assert_eq!(apu.rb(0xFF30), 0x12);
+	}
+	#[test]
+	fn test_length_counter() {
+		let mut apu = Apu::new();
+		apu.wb(0xFF26, 0x80);
+		apu.wb(0xFF17, 0xF0);
+		apu.wb(0xFF16, 0x3F); //length of 1
+		apu.wb(0xFF19, 0xC0); //trigger with length enabled
+		assert_eq!(apu.rb(0xFF26) & 2, 2);
+		apu.step(CYCLES_PER_SEQUENCER_STEP as i16);
+		assert_eq!(apu.rb(0xFF26) & 2, 0);
+	}
+	#[test]
+	fn test_sweep_overflow() {
+		let mut apu = Apu::new();
+		apu.wb(0xFF26, 0x80);
+		apu.wb(0xFF10, 0x11); //period 1, shift 1, increasing
+		apu.wb(0xFF12, 0xF0);
+		apu.wb(0xFF13, 0xFF);
+		apu.wb(0xFF14, 0x87); //frequency 0x7FF overflows immediately
+		assert_eq!(apu.rb(0xFF26) & 1, 0);
+	}
+	#[test]
+	fn test_produces_samples() {
+		let mut apu = Apu::new();
+		apu.wb(0xFF26, 0x80);
+		apu.wb(0xFF24, 0x77);
+		apu.wb(0xFF25, 0xFF);
+		apu.wb(0xFF12, 0xF0);
+		apu.wb(0xFF11, 0x80);
+		apu.wb(0xFF14, 0x87);
+		for _ in 0..1000 {
+			apu.step(16);
+		}
+		let samples = apu.take_samples();
+		assert_eq!(samples.len(), 2*16000/CYCLES_PER_SAMPLE as usize);
+		assert!(samples.iter().any(|&s| s.abs() > 0.1));
+		assert!(samples.iter().all(|&s| s.abs() <= 1.0));
+		assert!(apu.take_samples().is_empty());
+	}
+	#[test]
+	fn test_mute_and_solo() {
+		let mut apu = Apu::new();
+		apu.wb(0xFF26, 0x80);
+		apu.wb(0xFF24, 0x77);
+		apu.wb(0xFF25, 0xFF);
+		apu.wb(0xFF17, 0xF0);
+		apu.wb(0xFF16, 0x80);
+		apu.wb(0xFF19, 0x87);
+
+		apu.solo_channel(0);
+		assert_eq!([0, 1, 2, 3].iter().map(|&i| apu.is_channel_muted(i)).collect::<Vec<_>>(),
+				   vec![false, true, true, true]);
+		for _ in 0..1000 {
+			apu.step(16);
+		}
+		// Channel 2 is silent in the mix but still shows up in its scope
+		assert!(apu.take_samples().iter().all(|&s| s == 0.0));
+		assert!(apu.channel_scope(1).iter().any(|&s| s > 0.5));
+		assert!(apu.channel_scope(0).iter().all(|&s| s == 0.0));
+
+		apu.solo_channel(0);
+		assert!(!apu.is_channel_muted(1));
+		for _ in 0..1000 {
+			apu.step(16);
+		}
+		assert!(apu.take_samples().iter().any(|&s| s.abs() > 0.1));
+	}
+}
@@ -1,5 +1,7 @@
-use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::io::{Write, Read};
+
+use emulator::mbc::{Mapper, read_fill};
 
 //Simple cartridge with no memory banking
 pub struct Cartridge {
@@ -10,19 +12,24 @@ impl Cartridge {
 	pub fn new() -> Cartridge {
 		Cartridge{rom: [0; 0x8000]}
 	}
-	pub fn rb(&self, address: usize) -> u8 {
-	    self.rom[address as usize]
-	}
-	pub fn wb(&mut self, address: usize, val: u8) {
-		self.rom[address as usize] = val;
+}
+
+impl Mapper for Cartridge {
+	fn rb(&self, address: usize) -> u8 {
+		// There is no external RAM to read from
+		if address < 0x8000 {self.rom[address]} else {0xFF}
 	}
-	pub fn load_game(&mut self, game_file: &mut File) -> usize {
-		game_file.read(&mut self.rom).unwrap()
+	// ROM can't be written to, and there is nothing to control (though many games still write
+	// to 0x2000 as if selecting a bank)
+	fn wb(&mut self, _address: usize, _val: u8) {}
+	fn load_game(&mut self, game: &[u8]) -> usize {
+		read_fill(&mut &game[..], &mut self.rom).unwrap_or(0)
 	}
-	pub fn load_sav(&mut self, _: &mut File) -> usize {
-		0
+	// There is no state besides the ROM
+	fn save_state(&self, _out: &mut Write) -> io::Result<()> {
+		Ok(())
 	}
-	pub fn save_game(&mut self, _: &mut File) -> usize {
-		0
+	fn load_state(&mut self, _inp: &mut Read) -> io::Result<()> {
+		Ok(())
 	}
-}
\ No newline at end of file
+}
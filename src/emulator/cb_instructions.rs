@@ -10,13 +10,11 @@ macro_rules! new_cb_instruction {
 macro_rules! bit {
 	($shift:expr, hl) => {
     	|emu| {
-    		unsafe {
-    			let val = emu.mem.rb(*emu.regs.hl());
-    			emu.regs.update_flags(ZERO_FLAG, ((1 << $shift) & val) == 0);
-    		}
+			let val = emu.read_hl();
+			emu.regs.update_flags(ZERO_FLAG, ((1 << $shift) & val) == 0);
     		emu.regs.clear_flags(NEGATIVE_FLAG);
     		emu.regs.set_flags(HALFCARRY_FLAG);
-    		16
+    		12
     	}
     };
 
@@ -34,10 +32,8 @@ macro_rules! bit {
 macro_rules! set {
     ($shift:expr, hl) => {
     	|emu| {
-    		unsafe {
-    			let val = emu.mem.rb(*emu.regs.hl());
-    			emu.mem.wb(*emu.regs.hl(), val | (1 << $shift));
-    		}
+			let val = emu.read_hl();
+			emu.write_hl(val | (1 << $shift));
     		16
     	}
     };
@@ -53,18 +49,16 @@ macro_rules! set {
 macro_rules! rl {
 	(hl) => {
 		|emu| {
-			unsafe {
-				let carry = if emu.regs.get_flag(CARRY_FLAG) {1} else {0};
-				let val = emu.mem.rb(*emu.regs.hl());
-    			emu.regs.update_flags(CARRY_FLAG, (val & 0x80) > 0);
-
-    			emu.mem.wb(*emu.regs.hl(), (val << 1) | carry);
-
-    			let val = emu.mem.rb(*emu.regs.hl());
-    			emu.regs.update_flags(ZERO_FLAG, val == 0);
-    			emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
-    			16
-			}
+			let carry = if emu.regs.get_flag(CARRY_FLAG) {1} else {0};
+			let val = emu.read_hl();
+			emu.regs.update_flags(CARRY_FLAG, (val & 0x80) > 0);
+
+			let val = (val << 1) | carry;
+			emu.write_hl(val);
+
+			emu.regs.update_flags(ZERO_FLAG, val == 0);
+			emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
+			16
 		}
 	};
 
@@ -87,10 +81,8 @@ macro_rules! rl {
 macro_rules! res {
     ($shift:expr, hl) => {
     	|emu| {
-    		unsafe {
-    			let val = emu.mem.rb(*emu.regs.hl());
-    			emu.mem.wb(*emu.regs.hl(), val & !(1 << $shift));
-    		}
+			let val = emu.read_hl();
+			emu.write_hl(val & !(1 << $shift));
     		16
     	}
     };
@@ -106,14 +98,12 @@ macro_rules! res {
 macro_rules! swap {
 	(hl) => {
 		|emu| {
-			unsafe {
-				let val = emu.mem.rb(*emu.regs.hl());
-				emu.mem.wb(*emu.regs.hl(), ((val & 0x0F) << 4) | ((val & 0xF0) >> 4));
-
-				emu.regs.update_flags(ZERO_FLAG, val == 0);
-    			emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG | CARRY_FLAG);
-    			16
-			}
+			let val = emu.read_hl();
+			emu.write_hl(((val & 0x0F) << 4) | ((val & 0xF0) >> 4));
+
+			emu.regs.update_flags(ZERO_FLAG, val == 0);
+			emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG | CARRY_FLAG);
+			16
 		}
 	};
 
@@ -132,16 +122,14 @@ macro_rules! swap {
 macro_rules! sla {
     (hl) => {
     	|emu| {
-    		unsafe {
-	    		let val = emu.mem.rb(*emu.regs.hl());
-		    	let carry = (val & 0x80) > 0;
-		    	emu.mem.wb(*emu.regs.hl(), val << 1);
-
-		    	emu.regs.update_flags(ZERO_FLAG, val == 0);
-		    	emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
-		    	emu.regs.update_flags(CARRY_FLAG, carry);
-		    	16
-	    	}
+    		let val = emu.read_hl();
+	    	let carry = (val & 0x80) > 0;
+	    	emu.write_hl(val << 1);
+
+	    	emu.regs.update_flags(ZERO_FLAG, val == 0);
+	    	emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
+	    	emu.regs.update_flags(CARRY_FLAG, carry);
+	    	16
     	}
     };
 
@@ -162,16 +150,14 @@ macro_rules! sla {
 macro_rules! srl {
     (hl) => {
     	|emu| {
-    		unsafe {
-	    		let val = emu.mem.rb(*emu.regs.hl());
-		    	let carry = (val & 0x01) > 0;
-		    	emu.mem.wb(*emu.regs.hl(), val >> 1);
-
-		    	emu.regs.update_flags(ZERO_FLAG, val == 0);
-		    	emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
-		    	emu.regs.update_flags(CARRY_FLAG, carry);
-		    	16
-	    	}
+    		let val = emu.read_hl();
+	    	let carry = (val & 0x01) > 0;
+	    	emu.write_hl(val >> 1);
+
+	    	emu.regs.update_flags(ZERO_FLAG, val == 0);
+	    	emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
+	    	emu.regs.update_flags(CARRY_FLAG, carry);
+	    	16
     	}
     };
 
@@ -192,16 +178,14 @@ macro_rules! srl {
 macro_rules! rlc {
     (hl) => {
     	|emu| {
-    		unsafe {
-	    		let val = emu.mem.rb(*emu.regs.hl());
-	    		let carry = (val & 0x80) >> 7;
-	    		emu.mem.wb(*emu.regs.hl(), (val << 1) | carry);
-
-	    		emu.regs.update_flags(ZERO_FLAG, val == 0);
-	    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
-	    		emu.regs.update_flags(CARRY_FLAG, carry > 0);
-	    		16
-    		}
+    		let val = emu.read_hl();
+    		let carry = (val & 0x80) >> 7;
+    		emu.write_hl((val << 1) | carry);
+
+    		emu.regs.update_flags(ZERO_FLAG, val == 0);
+    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
+    		emu.regs.update_flags(CARRY_FLAG, carry > 0);
+    		16
     	}
     };
 
@@ -222,16 +206,14 @@ macro_rules! rlc {
 macro_rules! rrc {
     (hl) => {
     	|emu| {
-    		unsafe {
-    			let val = emu.mem.rb(*emu.regs.hl());
-	    		let carry = val & 0x01;
-	    		emu.mem.wb(*emu.regs.hl(), (val >> 1) | (carry << 7));
-
-	    		emu.regs.update_flags(ZERO_FLAG, val == 0);
-	    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
-	    		emu.regs.update_flags(CARRY_FLAG, carry > 0);
-	    		16
-    		}
+			let val = emu.read_hl();
+    		let carry = val & 0x01;
+    		emu.write_hl((val >> 1) | (carry << 7));
+
+    		emu.regs.update_flags(ZERO_FLAG, val == 0);
+    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
+    		emu.regs.update_flags(CARRY_FLAG, carry > 0);
+    		16
     	}
     };
 
@@ -252,16 +234,14 @@ macro_rules! rrc {
 macro_rules! rr {
     (hl) => {
     	|emu| {
-    		unsafe {
-    			let val = emu.mem.rb(*emu.regs.hl());
-	    		let carry = emu.regs.get_flag(CARRY_FLAG) as u8;
-	    		emu.mem.wb(*emu.regs.hl(), (val >> 1) | (carry << 7));
-
-	    		emu.regs.update_flags(ZERO_FLAG, val < 2 && carry == 0);
-	    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
-	    		emu.regs.update_flags(CARRY_FLAG, val & 0x01 > 0);
-	    		16
-    		}
+			let val = emu.read_hl();
+    		let carry = emu.regs.get_flag(CARRY_FLAG) as u8;
+    		emu.write_hl((val >> 1) | (carry << 7));
+
+    		emu.regs.update_flags(ZERO_FLAG, val < 2 && carry == 0);
+    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
+    		emu.regs.update_flags(CARRY_FLAG, val & 0x01 > 0);
+    		16
     	}
     };
 
@@ -282,16 +262,14 @@ macro_rules! rr {
 macro_rules! sra {
     (hl) => {
     	|emu| {
-    		unsafe {
-    			let val = emu.mem.rb(*emu.regs.hl());
-	    		let carry = val & 0x01 > 0;
-	    		emu.mem.wb(*emu.regs.hl(), (val >> 1) | (val & 0x80));
-
-	    		emu.regs.update_flags(ZERO_FLAG, val < 2);
-	    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
-	    		emu.regs.update_flags(CARRY_FLAG, carry);
-	    		16
-    		}
+			let val = emu.read_hl();
+    		let carry = val & 0x01 > 0;
+    		emu.write_hl((val >> 1) | (val & 0x80));
+
+    		emu.regs.update_flags(ZERO_FLAG, val < 2);
+    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG);
+    		emu.regs.update_flags(CARRY_FLAG, carry);
+    		16
     	}
     };
 
@@ -694,12 +672,12 @@ mod test {
 	fn test_rlc() {
 		let mut emu = Emulator::default();
 		unsafe {
-			*emu.regs.hl() = 0xFF1A;
-			emu.mem.wb(0xFF1A, 0x7A);
+			*emu.regs.hl() = 0xFF8A;
+			emu.mem.wb(0xFF8A, 0x7A);
 			let rlc_hl = CB_INSTRUCTIONS[0x06].func.unwrap();
 			rlc_hl(&mut emu);
-			assert_eq!(*emu.regs.hl(), 0xFF1A);
-			assert_eq!(emu.mem.rb(0xFF1A), 0xF4);
+			assert_eq!(*emu.regs.hl(), 0xFF8A);
+			assert_eq!(emu.mem.rb(0xFF8A), 0xF4);
 			assert_eq!(*emu.regs.f(), 0);
 		}
 	}
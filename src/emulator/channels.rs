@@ -0,0 +1,426 @@
+use std::io;
+use std::io::{Write, Read};
+
+use emulator::serialize::*;
+
+// The building blocks of the four sound channels
+// Each channel produces a digital value (0-15) which the APU then mixes
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+	[0, 0, 0, 0, 0, 0, 0, 1], //12.5%
+	[1, 0, 0, 0, 0, 0, 0, 1], //25%
+	[1, 0, 0, 0, 0, 1, 1, 1], //50%
+	[0, 1, 1, 1, 1, 1, 1, 0]  //75%
+];
+
+const NOISE_DIVISORS: [i32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Turns a channel off after a certain amount of time
+pub struct LengthCounter {
+	pub enabled:	bool,
+	pub counter:	u16,
+	max:			u16		//64 for most channels, 256 for the wave channel
+}
+
+impl LengthCounter {
+	pub fn new(max: u16) -> LengthCounter {
+		LengthCounter{enabled: false, counter: 0, max: max}
+	}
+	pub fn load(&mut self, val: u8) {
+		self.counter = self.max - (val as u16 & (self.max - 1));
+	}
+	pub fn trigger(&mut self) {
+		if self.counter == 0 {
+			self.counter = self.max;
+		}
+	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bool(out, self.enabled)?;
+		write_u16(out, self.counter)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.enabled = read_bool(inp)?;
+		self.counter = read_u16(inp)?;
+		Ok(())
+	}
+	// Returns true if the channel should be disabled
+	pub fn clock(&mut self) -> bool {
+		if self.enabled && self.counter > 0 {
+			self.counter -= 1;
+			return self.counter == 0;
+		}
+		false
+	}
+}
+
+// Periodically raises or lowers a channel's volume
+pub struct Envelope {
+	pub initial_volume:	u8,
+	pub increasing:		bool,
+	pub period:			u8,
+	pub volume:			u8,
+	timer:				u8
+}
+
+impl Envelope {
+	pub fn new() -> Envelope {
+		Envelope{initial_volume: 0, increasing: false, period: 0, volume: 0, timer: 0}
+	}
+	pub fn write(&mut self, val: u8) {
+		self.initial_volume = val >> 4;
+		self.increasing = val & 8 > 0;
+		self.period = val & 7;
+	}
+	// The DAC is off (and the channel silent) if the upper 5 bits of NRx2 are all 0
+	pub fn dac_enabled(&self) -> bool {
+		self.initial_volume > 0 || self.increasing
+	}
+	pub fn trigger(&mut self) {
+		self.timer = self.period;
+		self.volume = self.initial_volume;
+	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_u8(out, self.initial_volume)?;
+		write_bool(out, self.increasing)?;
+		write_u8(out, self.period)?;
+		write_u8(out, self.volume)?;
+		write_u8(out, self.timer)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.initial_volume = read_u8(inp)?;
+		self.increasing = read_bool(inp)?;
+		self.period = read_u8(inp)?;
+		self.volume = read_u8(inp)?;
+		self.timer = read_u8(inp)?;
+		Ok(())
+	}
+	pub fn clock(&mut self) {
+		if self.period == 0 {
+			return;
+		}
+		self.timer = self.timer.saturating_sub(1);
+		if self.timer == 0 {
+			self.timer = self.period;
+			if self.increasing && self.volume < 15 {
+				self.volume += 1;
+			} else if !self.increasing && self.volume > 0 {
+				self.volume -= 1;
+			}
+		}
+	}
+}
+
+// Periodically raises or lowers channel 1's frequency
+pub struct Sweep {
+	pub period:		u8,
+	pub negate:		bool,
+	pub shift:		u8,
+	enabled:		bool,
+	shadow:			u16,
+	timer:			u8
+}
+
+impl Sweep {
+	pub fn new() -> Sweep {
+		Sweep{period: 0, negate: false, shift: 0, enabled: false, shadow: 0, timer: 0}
+	}
+	pub fn write(&mut self, val: u8) {
+		self.period = (val >> 4) & 7;
+		self.negate = val & 8 > 0;
+		self.shift = val & 7;
+	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_u8(out, self.period)?;
+		write_bool(out, self.negate)?;
+		write_u8(out, self.shift)?;
+		write_bool(out, self.enabled)?;
+		write_u16(out, self.shadow)?;
+		write_u8(out, self.timer)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.period = read_u8(inp)? & 7;
+		self.negate = read_bool(inp)?;
+		self.shift = read_u8(inp)? & 7;
+		self.enabled = read_bool(inp)?;
+		self.shadow = read_u16(inp)? & 0x7FF;
+		self.timer = read_u8(inp)?;
+		Ok(())
+	}
+	fn reload_timer(&mut self) {
+		self.timer = if self.period == 0 {8} else {self.period};
+	}
+	fn calculate(&self) -> u16 {
+		let delta = self.shadow >> self.shift;
+		if self.negate {self.shadow - delta} else {self.shadow + delta}
+	}
+	// Returns false if the new frequency overflowed and the channel should be disabled
+	pub fn trigger(&mut self, frequency: u16) -> bool {
+		self.shadow = frequency;
+		self.reload_timer();
+		self.enabled = self.period > 0 || self.shift > 0;
+		self.shift == 0 || self.calculate() <= 2047
+	}
+	// Returns the new frequency (if it changed) and false if the channel should be disabled
+	pub fn clock(&mut self) -> (Option<u16>, bool) {
+		self.timer = self.timer.saturating_sub(1);
+		if self.timer > 0 {
+			return (None, true);
+		}
+		self.reload_timer();
+		if !self.enabled || self.period == 0 {
+			return (None, true);
+		}
+
+		let frequency = self.calculate();
+		if frequency > 2047 {
+			return (None, false);
+		}
+		if self.shift == 0 {
+			return (None, true);
+		}
+		self.shadow = frequency;
+		(Some(frequency), self.calculate() <= 2047)
+	}
+}
+
+// Channels 1 and 2
+pub struct SquareChannel {
+	pub enabled:	bool,
+	pub duty:		u8,
+	pub frequency:	u16,
+	pub length:		LengthCounter,
+	pub envelope:	Envelope,
+	pub sweep:		Sweep,
+	duty_pos:		usize,
+	timer:			i32
+}
+
+impl SquareChannel {
+	pub fn new() -> SquareChannel {
+		SquareChannel {
+			enabled: false,
+			duty: 0,
+			frequency: 0,
+			length: LengthCounter::new(64),
+			envelope: Envelope::new(),
+			sweep: Sweep::new(),
+			duty_pos: 0,
+			timer: 0
+		}
+	}
+	fn period(&self) -> i32 {
+		(2048 - self.frequency as i32) * 4
+	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bool(out, self.enabled)?;
+		write_u8(out, self.duty)?;
+		write_u16(out, self.frequency)?;
+		self.length.save_state(out)?;
+		self.envelope.save_state(out)?;
+		self.sweep.save_state(out)?;
+		write_u8(out, self.duty_pos as u8)?;
+		write_i32(out, self.timer)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.enabled = read_bool(inp)?;
+		self.duty = read_u8(inp)? & 3;
+		self.frequency = read_u16(inp)? & 0x7FF;
+		self.length.load_state(inp)?;
+		self.envelope.load_state(inp)?;
+		self.sweep.load_state(inp)?;
+		self.duty_pos = read_u8(inp)? as usize % 8;
+		self.timer = read_i32(inp)?;
+		Ok(())
+	}
+	pub fn trigger(&mut self, has_sweep: bool) {
+		self.enabled = self.envelope.dac_enabled();
+		self.length.trigger();
+		self.envelope.trigger();
+		self.timer = self.period();
+		if has_sweep && !self.sweep.trigger(self.frequency) {
+			self.enabled = false;
+		}
+	}
+	pub fn clock_sweep(&mut self) {
+		let (frequency, ok) = self.sweep.clock();
+		if let Some(frequency) = frequency {
+			self.frequency = frequency;
+		}
+		if !ok {
+			self.enabled = false;
+		}
+	}
+	pub fn step(&mut self, cycles: i32) {
+		self.timer -= cycles;
+		while self.timer <= 0 {
+			self.timer += self.period();
+			self.duty_pos = (self.duty_pos + 1) % 8;
+		}
+	}
+	pub fn output(&self) -> u8 {
+		if self.enabled {
+			DUTY_PATTERNS[self.duty as usize][self.duty_pos] * self.envelope.volume
+		} else {
+			0
+		}
+	}
+}
+
+// Channel 3
+pub struct WaveChannel {
+	pub enabled:		bool,
+	pub dac_enabled:	bool,
+	pub volume_code:	u8,
+	pub frequency:		u16,
+	pub length:			LengthCounter,
+	pub wave_ram:		[u8; 16],
+	position:			usize,
+	timer:				i32
+}
+
+impl WaveChannel {
+	pub fn new() -> WaveChannel {
+		WaveChannel {
+			enabled: false,
+			dac_enabled: false,
+			volume_code: 0,
+			frequency: 0,
+			length: LengthCounter::new(256),
+			wave_ram: [0; 16],
+			position: 0,
+			timer: 0
+		}
+	}
+	fn period(&self) -> i32 {
+		(2048 - self.frequency as i32) * 2
+	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bool(out, self.enabled)?;
+		write_bool(out, self.dac_enabled)?;
+		write_u8(out, self.volume_code)?;
+		write_u16(out, self.frequency)?;
+		self.length.save_state(out)?;
+		write_bytes(out, &self.wave_ram)?;
+		write_u8(out, self.position as u8)?;
+		write_i32(out, self.timer)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.enabled = read_bool(inp)?;
+		self.dac_enabled = read_bool(inp)?;
+		self.volume_code = read_u8(inp)? & 3;
+		self.frequency = read_u16(inp)? & 0x7FF;
+		self.length.load_state(inp)?;
+		read_bytes(inp, &mut self.wave_ram)?;
+		self.position = read_u8(inp)? as usize % 32;
+		self.timer = read_i32(inp)?;
+		Ok(())
+	}
+	pub fn trigger(&mut self) {
+		self.enabled = self.dac_enabled;
+		self.length.trigger();
+		self.timer = self.period();
+		self.position = 0;
+	}
+	pub fn step(&mut self, cycles: i32) {
+		self.timer -= cycles;
+		while self.timer <= 0 {
+			self.timer += self.period();
+			self.position = (self.position + 1) % 32;
+		}
+	}
+	pub fn output(&self) -> u8 {
+		if !self.enabled {
+			return 0;
+		}
+		let byte = self.wave_ram[self.position/2];
+		let sample = if self.position%2 == 0 {byte >> 4} else {byte & 0xF};
+		match self.volume_code {
+			0 => 0,
+			1 => sample,
+			2 => sample >> 1,
+			_ => sample >> 2
+		}
+	}
+}
+
+// Channel 4
+pub struct NoiseChannel {
+	pub enabled:	bool,
+	pub shift:		u8,
+	pub width_mode:	bool,	//true for 7-bit LFSR
+	pub divisor:	u8,
+	pub length:		LengthCounter,
+	pub envelope:	Envelope,
+	lfsr:			u16,
+	timer:			i32
+}
+
+impl NoiseChannel {
+	pub fn new() -> NoiseChannel {
+		NoiseChannel {
+			enabled: false,
+			shift: 0,
+			width_mode: false,
+			divisor: 0,
+			length: LengthCounter::new(64),
+			envelope: Envelope::new(),
+			lfsr: 0x7FFF,
+			timer: 0
+		}
+	}
+	pub fn write_polynomial(&mut self, val: u8) {
+		self.shift = val >> 4;
+		self.width_mode = val & 8 > 0;
+		self.divisor = val & 7;
+	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bool(out, self.enabled)?;
+		write_u8(out, self.shift)?;
+		write_bool(out, self.width_mode)?;
+		write_u8(out, self.divisor)?;
+		self.length.save_state(out)?;
+		self.envelope.save_state(out)?;
+		write_u16(out, self.lfsr)?;
+		write_i32(out, self.timer)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.enabled = read_bool(inp)?;
+		self.shift = read_u8(inp)? & 0xF;
+		self.width_mode = read_bool(inp)?;
+		self.divisor = read_u8(inp)? & 7;
+		self.length.load_state(inp)?;
+		self.envelope.load_state(inp)?;
+		self.lfsr = read_u16(inp)?;
+		self.timer = read_i32(inp)?;
+		Ok(())
+	}
+	fn period(&self) -> i32 {
+		NOISE_DIVISORS[self.divisor as usize] << self.shift
+	}
+	pub fn trigger(&mut self) {
+		self.enabled = self.envelope.dac_enabled();
+		self.length.trigger();
+		self.envelope.trigger();
+		self.timer = self.period();
+		self.lfsr = 0x7FFF;
+	}
+	pub fn step(&mut self, cycles: i32) {
+		self.timer -= cycles;
+		while self.timer <= 0 {
+			self.timer += self.period();
+
+			let xor = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+			self.lfsr = (self.lfsr >> 1) | (xor << 14);
+			if self.width_mode {
+				self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+			}
+		}
+	}
+	pub fn output(&self) -> u8 {
+		if self.enabled && self.lfsr & 1 == 0 {
+			self.envelope.volume
+		} else {
+			0
+		}
+	}
+}
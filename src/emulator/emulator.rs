@@ -1,24 +1,28 @@
 use std::fmt;
+use std::io;
 use std::fs::File;
-use std::io::SeekFrom;
 use std::io::prelude::*;
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 
 use emulator::Memory;
 use emulator::Gpu;
 use emulator::InterruptManager;
 use emulator::Timers;
+use emulator::Apu;
+use emulator::SerialDevice;
+use emulator::{LoadError, MapperFactory};
 use emulator::mbc::*;
 use emulator::instructions::*;
 use emulator::registers::*;
 use emulator::rom_info::*;
-
-use super::super::programstate::*;
+use emulator::serialize::*;
 
 fn to_save(game: String) -> String {
-	let dot_pos = game.rfind('.').unwrap();
 	// Not sure how regular .sav files are saved so these are .rsav
-	game[..dot_pos].to_string() + ".rsav"
+	match game.rfind('.') {
+		Some(dot_pos) => game[..dot_pos].to_string() + ".rsav",
+		None 		  => game + ".rsav"
+	}
 }
 
 fn to_null_terminated(bytes: &[u8]) -> String {
@@ -29,15 +33,39 @@ fn to_null_terminated(bytes: &[u8]) -> String {
 							.to_string()
 }
 
+// Save states start with this, followed by STATE_VERSION
+const STATE_MAGIC: &'static [u8; 4] = b"RGBS";
+// Bump this whenever what goes into a save state changes
+pub const STATE_VERSION: u32 = 5;
+
+// A real Gameboy takes this many cycles to draw a frame (about 59.73 frames a second)
+pub const CYCLES_PER_FRAME: u64 = 70224;
+// How long the CPU stays paused after STOP switches a CGB's speed
+const SPEED_SWITCH_CYCLES: u64 = 8200;
+
+// Things that happen during emulation which a frontend may want to react to (e.g. by pausing)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+	// The BIOS finished running and control was handed to the game
+	// This happens exactly once (and never if the BIOS was skipped), so it takes precedence over
+	// any other event from the same step
+	BiosFinished,
+	// A (detectable) infinite loop was entered at the given address
+	InfiniteLoop(u16),
+	// The opcode at the given address has no implementation
+	UnimplementedInstruction(u16, u8),
+}
+
 pub struct Emulator {
 	clock: u64,
-	interrupts: InterruptManager,
+	pub(in emulator) interrupts: InterruptManager,
 	controls: [u8; 8],
 	timers:	Timers,
 	cgb_mode: bool,
-	bios_breakpoint: bool,
-	unimpl_instr_breakpoint: bool,
-	inf_loop_breakpoint: bool,
+	mappers: HashMap<u8, MapperFactory>,
+	rom_hash: u64,
+	// Cycles the rest of the machine has been ticked during the current step
+	step_cycles: u64,
 
 	// TODO: Maybe make these not public and replace with (specialized) getters/setters
 	//       This might be fine as is since they're only public in the emulator module
@@ -45,7 +73,12 @@ pub struct Emulator {
 	pub(in emulator) gpu: Gpu,
 	pub(in emulator) regs: Registers,
 	pub(in emulator) halted: bool,
-	pub(in emulator) stopped: bool
+	// Set when HALT is executed with IME off and an interrupt already pending, in which case the
+	// CPU doesn't halt but the byte after HALT gets read twice
+	pub(in emulator) halt_bug: bool,
+	pub(in emulator) stopped: bool,
+	// Cycles left until the CPU starts running again after a speed switch
+	speed_switch: u64
 }
 
 impl fmt::Debug for Emulator {
@@ -84,12 +117,12 @@ impl fmt::Debug for Emulator {
 
 impl Default for Emulator {
 	fn default() -> Emulator {
-		Emulator::new(false, false, false)
+		Emulator::new()
 	}
 }
 
 impl Emulator {
-	pub fn new(bios_breakpoint: bool, unimpl_instr_breakpoint: bool, inf_loop_breakpoint: bool) -> Emulator {
+	pub fn new() -> Emulator {
 		Emulator {
 			clock: 0, 
 			mem: Memory::new(), 
@@ -97,13 +130,15 @@ impl Emulator {
 			controls: [0; 8], 
 			regs: Registers::new(), 
 			halted: false, 
+			halt_bug: false,
 			timers: Timers::new(),
 			interrupts: InterruptManager::new(), 
 			stopped: false, 
 			cgb_mode: false,
-			bios_breakpoint: bios_breakpoint,
-			unimpl_instr_breakpoint: unimpl_instr_breakpoint,
-			inf_loop_breakpoint: inf_loop_breakpoint,
+			mappers: HashMap::new(),
+			rom_hash: 0,
+			step_cycles: 0,
+			speed_switch: 0,
 		}
 	}
 	pub fn get_speed(&self) -> u64 {
@@ -120,106 +155,146 @@ impl Emulator {
 			self.controls[i] = controls[i];
 		}
 	}
-	pub fn load_bios(&mut self, path: String) {
+	// Use factory to create the mapper for games with the given cartridge type (header byte 0x147)
+	// This takes priority over the built-in mappers
+	pub fn register_mapper(&mut self, cartridge_type: u8, factory: MapperFactory) {
+		self.mappers.insert(cartridge_type, factory);
+	}
+	// If this returns an error, the emulator should be set up with skip_bios instead
+	pub fn load_bios(&mut self, path: String) -> Result<(), LoadError> {
 		println!("Loading BIOS from \"{}\"...", path);
-		match File::open(path) {
-			Ok(mut bios_file) => {
-				println!("Found BIOS");
-				let _ = bios_file.read_to_end(&mut self.mem.bios);
-				println!("Successfully loaded bios\n");
-			},
-			Err(_) => {
-				println!("Could not find BIOS");
-				println!("Manually initializing emulator...");
-
-				unsafe {
-					*self.regs.af() = 0x11B0;
-					*self.regs.bc() = 0x0013;
-					*self.regs.de() = 0x00D8;
-					*self.regs.hl() = 0x014D;
-				}
-				self.regs.sp = 0xFFFE;
-				self.regs.pc = 0x0100;
-
- 				self.mem.wb(0xFF05, 0x00);
-				self.mem.wb(0xFF06, 0x00);
-				self.mem.wb(0xFF07, 0x00);
-				self.mem.wb(0xFF10, 0x80);
-				self.mem.wb(0xFF11, 0xBF);
-				self.mem.wb(0xFF12, 0xF3);
-				self.mem.wb(0xFF14, 0xBF);
-				self.mem.wb(0xFF16, 0x3F);
-				self.mem.wb(0xFF17, 0x00);
-				self.mem.wb(0xFF19, 0xBF);
-				self.mem.wb(0xFF1A, 0x7F);
-				self.mem.wb(0xFF1B, 0xFF);
-				self.mem.wb(0xFF1C, 0x9F);
-				self.mem.wb(0xFF1E, 0xBF);
-				self.mem.wb(0xFF20, 0xFF);
-				self.mem.wb(0xFF21, 0x00);
-				self.mem.wb(0xFF22, 0x00);
-				self.mem.wb(0xFF23, 0xBF);
-				self.mem.wb(0xFF24, 0x77);
-				self.mem.wb(0xFF25, 0xF3);
-				self.mem.wb(0xFF26, 0xF1);
-				self.mem.wb(0xFF40, 0x91);
-				self.mem.wb(0xFF42, 0x00);
-				self.mem.wb(0xFF43, 0x00);
-				self.mem.wb(0xFF45, 0x00);
-				self.mem.wb(0xFF47, 0xFC);
-				self.mem.wb(0xFF48, 0xFF);
-				self.mem.wb(0xFF49, 0xFF);
-				self.mem.wb(0xFF4A, 0x00);
-				self.mem.wb(0xFF4B, 0x00);
-				self.mem.wb(0xFFFF, 0x00);
-
-				self.mem.finished_with_bios();
-				println!("Emulator initialized\n");
-			}
-		}
+		self.load_bios_from(&mut File::open(path)?)
 	}
-	pub fn load_game(&mut self, path: String) {
-		println!("Loading game from \"{}\"...", path);
-		let mut game_file = File::open(path.clone()).unwrap();
-		
-		let mut header = [0; 0x150];
-		let _ = game_file.read(&mut header).unwrap();
-		let _ = game_file.seek(SeekFrom::Start(0));
+	pub fn load_bios_from<R: Read>(&mut self, bios: &mut R) -> Result<(), LoadError> {
+		let mut data = Vec::new();
+		bios.read_to_end(&mut data)?;
+		self.load_bios_from_bytes(&data)
+	}
+	pub fn load_bios_from_bytes(&mut self, bios: &[u8]) -> Result<(), LoadError> {
+		self.mem.bios = bios.to_vec();
+		println!("Successfully loaded bios\n");
+		Ok(())
+	}
+	// Puts the emulator in the state the BIOS would have left it in
+	pub fn skip_bios(&mut self) {
+		println!("Manually initializing emulator...");
 
-		let title = to_null_terminated(&header[0x134..0x144]);
+		unsafe {
+			*self.regs.af() = 0x11B0;
+			*self.regs.bc() = 0x0013;
+			*self.regs.de() = 0x00D8;
+			*self.regs.hl() = 0x014D;
+		}
+		self.regs.sp = 0xFFFE;
+		self.regs.pc = 0x0100;
 
-		println!("The title of the game is {}", title);
-		
-		let cartridge_type = header[0x147];
-		let cartridge_type = match CartridgeType::from_code(cartridge_type) {
-			Some(t) => t,
-			None  	=> panic!("Unknown cartridge type: {:#X}", cartridge_type)
-		};
-		println!("The cartridge type is {:?}", cartridge_type);
+		self.mem.wb(0xFF05, 0x00);
+		self.mem.wb(0xFF06, 0x00);
+		self.mem.wb(0xFF07, 0x00);
+		// The APU ignores writes to its other registers while it is off
+		self.mem.wb(0xFF26, 0xF1);
+		self.mem.wb(0xFF10, 0x80);
+		self.mem.wb(0xFF11, 0xBF);
+		self.mem.wb(0xFF12, 0xF3);
+		self.mem.wb(0xFF14, 0xBF);
+		self.mem.wb(0xFF16, 0x3F);
+		self.mem.wb(0xFF17, 0x00);
+		self.mem.wb(0xFF19, 0xBF);
+		self.mem.wb(0xFF1A, 0x7F);
+		self.mem.wb(0xFF1B, 0xFF);
+		self.mem.wb(0xFF1C, 0x9F);
+		self.mem.wb(0xFF1E, 0xBF);
+		self.mem.wb(0xFF20, 0xFF);
+		self.mem.wb(0xFF21, 0x00);
+		self.mem.wb(0xFF22, 0x00);
+		self.mem.wb(0xFF23, 0xBF);
+		self.mem.wb(0xFF24, 0x77);
+		self.mem.wb(0xFF25, 0xF3);
+		self.mem.wb(0xFF40, 0x91);
+		self.mem.wb(0xFF42, 0x00);
+		self.mem.wb(0xFF43, 0x00);
+		self.mem.wb(0xFF45, 0x00);
+		self.mem.wb(0xFF47, 0xFC);
+		self.mem.wb(0xFF48, 0xFF);
+		self.mem.wb(0xFF49, 0xFF);
+		self.mem.wb(0xFF4A, 0x00);
+		self.mem.wb(0xFF4B, 0x00);
+		self.mem.wb(0xFFFF, 0x00);
 
-		self.mem.cart = Mbc::new(cartridge_type);
-		self.mem.cart.load_game(&mut game_file);
-		self.mem.save_file = to_save(path);
+		self.mem.finished_with_bios();
+		println!("Emulator initialized\n");
+	}
+	// Loads a game along with its .rsav save file (if there is one)
+	// If only the save file can't be read, the game is still loaded and LoadError::BadSave is returned
+	pub fn load_game(&mut self, path: String) -> Result<(), LoadError> {
+		println!("Loading game from \"{}\"...", path);
+		self.load_game_from(&mut File::open(path.clone())?)?;
 
-		if let Ok(mut file) = File::open(self.mem.save_file.clone()) {
-			println!("Loading .rsav save file from {}", self.mem.save_file);
-			self.mem.cart.load_sav(&mut file);
+		let save_file = to_save(path);
+		let mut save = Vec::new();
+		match File::open(&save_file).and_then(|mut file| file.read_to_end(&mut save)) {
+			Ok(_) => {
+				println!("Loading .rsav save file from {}", save_file);
+				self.load_sram(&mut &save[..])?;
+			},
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+			// Leaving save_file empty means the unreadable save won't be overwritten either
+			Err(e) => return Err(LoadError::BadSave(e))
+		}
+		self.mem.save_file = save_file;
+		Ok(())
+	}
+	pub fn load_game_from<R: Read>(&mut self, game: &mut R) -> Result<(), LoadError> {
+		let mut data = Vec::new();
+		game.read_to_end(&mut data)?;
+		self.load_game_from_bytes(&data)
+	}
+	pub fn load_game_from_bytes(&mut self, game: &[u8]) -> Result<(), LoadError> {
+		if game.len() < 0x150 {
+			return Err(LoadError::TruncatedRom{expected: 0x150, actual: game.len()});
 		}
+		let header = &game[..0x150];
+
+		let title = to_null_terminated(&header[0x134..0x144]);
+		println!("The title of the game is {}", title);
 
 		let rom_size = header[0x148];
 		let rom_size = match get_rom_size(rom_size) {
 			Some(size) 	=> size * 1024,
-			None 		=> panic!("Unkown ROM size type: {}", rom_size)
+			None 		=> return Err(LoadError::BadHeader(format!("Unknown ROM size type: {}", rom_size)))
 		};
-		println!("{} has {} bytes ({} KB) of ROM", title, rom_size, rom_size/1024);
+		if game.len() < rom_size {
+			return Err(LoadError::TruncatedRom{expected: rom_size, actual: game.len()});
+		}
 
 		let ram_size = header[0x149];
 		let ram_size = match get_ram_size(ram_size) {
 			Some(size)	=> size * 1024,
-			None		=> panic!("Unknown RAM size type: {}", ram_size)
+			None		=> return Err(LoadError::BadHeader(format!("Unknown RAM size type: {}", ram_size)))
+		};
+
+		let cartridge_code = header[0x147];
+		let mut cart = match self.mappers.get(&cartridge_code) {
+			Some(factory) => {
+				println!("Using custom mapper for cartridge type {:#X}", cartridge_code);
+				factory(rom_size, ram_size)
+			},
+			None => {
+				let cartridge_type = CartridgeType::from_code(cartridge_code)
+					.ok_or(LoadError::UnsupportedMapper(cartridge_code))?;
+				println!("The cartridge type is {:?}", cartridge_type);
+				new_mapper(cartridge_type, rom_size, ram_size).ok_or(LoadError::UnsupportedMapper(cartridge_code))?
+			}
 		};
+
+		println!("{} has {} bytes ({} KB) of ROM", title, rom_size, rom_size/1024);
 		println!("{} has {} bytes ({} KB) of external RAM", title, ram_size, ram_size/1024);
 
+		cart.load_game(game);
+		self.mem.cart = cart;
+		self.rom_hash = hash_bytes(game);
+		self.mem.save_file = String::new();
+
 		println!("Successfully loaded {}\n", title);
 
 		self.cgb_mode = if self.mem.bios.len() == 0 && header[0x143] & 0x80 == 0 {
@@ -230,96 +305,244 @@ impl Emulator {
 		};
 		self.mem.cgb_mode = self.cgb_mode;
 		println!("Emulator running in {}CGB mode", if self.cgb_mode {""} else {"Non-"});
-	}
-	pub(in emulator) fn enable_interrupts(&mut self) {
-		self.interrupts.ime = true;
-	}
-	pub(in emulator) fn disable_interrupts(&mut self) {
-		self.interrupts.ime = false;
+		Ok(())
 	}
 	pub fn update_keys(&mut self, key: u8, pressed: bool) {
-		let old_state = self.mem.rb(0xFF00);
+		let mut buttons = self.get_joypad();
 		for i in 0..8 {
 			if self.controls[i] == key {
-				self.mem.wk(i as u8, pressed);
-				let new_state = self.mem.rb(0xFF00);
-				if (!new_state & old_state & (1 << i%4)) > 0 {
-					self.interrupts.request_interrupt(&mut self.mem, 4);
-				}
+				buttons = if pressed {buttons | 1 << i} else {buttons & !(1 << i)};
 			}
 		}
+		self.set_joypad(buttons);
+	}
+	// Which buttons are held down, one bit each (set if pressed)
+	// In order from bit 0, they are right, left, up, down, A, B, select and start
+	pub fn get_joypad(&self) -> u8 {
+		!self.mem.key_state()
 	}
-	pub fn step(&mut self, state: &mut ProgramState, dstate: &mut DebugState) -> u64 {
-		let cycles = if !self.halted && !self.stopped {self.emulate_cycle(state, dstate)} else {40};
-		self.gpu.step(&mut self.mem, &self.interrupts, cycles as i16, self.cgb_mode);
-		self.timers.step(&mut self.mem, &self.interrupts, cycles as i16);
-		self.mem.cart.step(cycles as i16);
-		if self.interrupts.step(&mut self.mem, &mut self.regs) {
+	pub fn set_joypad(&mut self, buttons: u8) {
+		let old_buttons = self.get_joypad();
+		let old_state = self.mem.rb(0xFF00);
+		for i in 0..8 {
+			self.mem.wk(i, buttons & (1 << i) > 0);
+		}
+		let new_state = self.mem.rb(0xFF00);
+		// Pressing anything brings the Gameboy out of STOP
+		if self.stopped && buttons & !old_buttons > 0 {
+			self.stopped = false;
+		}
+		if (!new_state & old_state & 0xF) > 0 {
+			self.interrupts.request_interrupt(&mut self.mem, 4);
+		}
+	}
+	// Identifies the loaded ROM (0 if there isn't one)
+	pub fn rom_hash(&self) -> u64 {
+		self.rom_hash
+	}
+	// The cartridge's real time clock registers (seconds, minutes, hours, day low, day high), if it has one
+	// The clock only advances with emulated time, so setting it is the only way to change the date
+	pub fn get_rtc(&self) -> Option<[u8; 5]> {
+		self.mem.cart.rtc()
+	}
+	pub fn set_rtc(&mut self, rtc: [u8; 5]) {
+		self.mem.cart.set_rtc(rtc)
+	}
+	// Returns the number of cycles that passed, along with anything noteworthy that happened
+	pub fn step(&mut self) -> (u64, Option<Event>) {
+		self.step_cycles = 0;
+		let mut event = if self.halted {
+			// Any interrupt that is requested and enabled wakes the CPU up, even if it won't be serviced
+			self.tick(4);
+			if self.interrupts.pending(&self.mem) {
+				self.halted = false;
+			}
+			None
+		} else if self.stopped {
+			// Everything's clock is stopped, so only the time passes
+			self.step_cycles += 4;
+			None
+		} else if self.speed_switch > 0 {
+			self.tick(4);
+			self.speed_switch -= 4;
+			None
+		} else {
+			let event = self.emulate_cycle();
+			self.interrupts.instruction_finished();
+			event
+		};
+		if !self.stopped && self.speed_switch == 0 && self.dispatch_interrupt() {
 			self.halted = false;
 		}
+		let cycles = self.step_cycles;
 
-		if self.regs.pc == 0x100 {
+		if self.regs.pc == 0x100 && self.mem.is_running_bios() {
 			self.mem.finished_with_bios();
-			if self.bios_breakpoint {
-				state.paused = true;
-			}
+			event = Some(Event::BiosFinished);
+		}
+		(cycles, event)
+	}
+	// Runs the emulator for (at least) one frame's worth of cycles, ignoring any events
+	// Returns the number of cycles that passed
+	pub fn run_frame(&mut self) -> u64 {
+		let mut cycles = 0;
+		while cycles < CYCLES_PER_FRAME*self.get_speed() {
+			cycles += self.step().0;
 		}
 		cycles
 	}
+	// Writes battery-backed RAM to the .rsav file next to the game (if the game was loaded from a file)
 	pub fn save_game(&mut self) -> usize {
+		let sram = self.export_sram();
+		if self.mem.save_file.is_empty() || sram.is_empty() {
+			return 0;
+		}
 		if let Ok(mut file) = File::create(self.mem.save_file.clone()) {
-			self.mem.cart.save_game(&mut file)
+			file.write_all(&sram).map(|_| sram.len()).unwrap_or(0)
 		} else {
 			0
 		}
 	}
+	pub fn load_sram<R: Read>(&mut self, save: &mut R) -> io::Result<usize> {
+		self.mem.cart.load_sav(save)
+	}
+	pub fn save_sram<W: Write>(&self, save: &mut W) -> io::Result<usize> {
+		self.mem.cart.save_game(save)
+	}
+	pub fn export_sram(&self) -> Vec<u8> {
+		let mut sram = Vec::new();
+		let _ = self.save_sram(&mut sram);
+		sram
+	}
+	// The part of the cartridge header (title, licensee, version, checksums...) used to tell games apart
+	fn game_id(&self) -> Vec<u8> {
+		(0x134..0x150).map(|address| self.mem.cart.rb(address)).collect()
+	}
+	// Writes a snapshot of the entire machine (but not the ROM or BIOS themselves)
+	pub fn save_state<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		out.write_all(STATE_MAGIC)?;
+		write_u32(out, STATE_VERSION)?;
+		write_bytes(out, &self.game_id())?;
+		self.write_state(out)
+	}
+	// Restores a snapshot written by save_state
+	// The same game must already be loaded, and if anything goes wrong the emulator is left as it was
+	pub fn load_state<R: Read>(&mut self, inp: &mut R) -> Result<(), LoadError> {
+		let mut magic = [0; 4];
+		inp.read_exact(&mut magic)?;
+		if &magic != STATE_MAGIC {
+			return Err(LoadError::BadSaveState("not a save state".to_string()));
+		}
+		let version = read_u32(inp)?;
+		if version != STATE_VERSION {
+			return Err(LoadError::BadSaveState(format!("version {} is not supported (expected {})", version, STATE_VERSION)));
+		}
+		let mut game_id = self.game_id();
+		if read_bytes(inp, &mut game_id).is_err() || game_id != self.game_id() {
+			return Err(LoadError::BadSaveState("made with a different game".to_string()));
+		}
+
+		let mut backup = Vec::new();
+		self.write_state(&mut backup)?;
+		self.read_state(inp).map_err(|err| {
+			let _ = self.read_state(&mut &backup[..]);
+			match err.kind() {
+				io::ErrorKind::InvalidData => LoadError::BadSaveState(err.to_string()),
+				_ => LoadError::from(err)
+			}
+		})
+	}
+	fn write_state(&self, out: &mut Write) -> io::Result<()> {
+		write_u64(out, self.clock)?;
+		write_bool(out, self.cgb_mode)?;
+		write_bool(out, self.halted)?;
+		write_bool(out, self.halt_bug)?;
+		write_bool(out, self.stopped)?;
+		write_u64(out, self.speed_switch)?;
+		self.regs.save_state(out)?;
+		self.interrupts.save_state(out)?;
+		self.timers.save_state(out)?;
+		self.gpu.save_state(out)?;
+		self.mem.save_state(out)
+	}
+	fn read_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.clock = read_u64(inp)?;
+		self.cgb_mode = read_bool(inp)?;
+		self.halted = read_bool(inp)?;
+		self.halt_bug = read_bool(inp)?;
+		self.stopped = read_bool(inp)?;
+		self.speed_switch = read_u64(inp)?;
+		// step counts this down 4 cycles at a time
+		if self.speed_switch%4 != 0 || self.speed_switch > SPEED_SWITCH_CYCLES {
+			return Err(io::Error::new(io::ErrorKind::InvalidData,
+									  format!("{} cycles left in a speed switch", self.speed_switch)));
+		}
+		self.regs.load_state(inp)?;
+		self.interrupts.load_state(inp)?;
+		self.timers.load_state(inp)?;
+		self.gpu.load_state(inp)?;
+		self.mem.load_state(inp)
+	}
+	pub fn export_state(&self) -> Vec<u8> {
+		let mut state = Vec::new();
+		let _ = self.save_state(&mut state);
+		state
+	}
+	pub fn import_state(&mut self, state: &[u8]) -> Result<(), LoadError> {
+		self.load_state(&mut &state[..])
+	}
 	// Needs some cleaning up
 	// Doesn't produce perfectly correct output, and is messy code
 	pub fn disassemble_file(file: &str) -> String {
 		if let Ok(mut file) = File::open(file) {
-			let mut disassembly = String::new();
-
 			let mut data = Vec::new();
 			let _ = file.read_to_end(&mut data);
+			Emulator::disassemble_rom(&data)
+		} else {
+			String::new()
+		}
+	}
+	pub fn disassemble_rom(data: &[u8]) -> String {
+		let mut disassembly = String::new();
+		if data.len() < 0x150 {
+			return disassembly;
+		}
 
-			// Assume there is a NOP followed by a JP at address 0x100
-			let start = data[0x102] as usize | ((data[0x103] as usize) << 8);
+		// Assume there is a NOP followed by a JP at address 0x100
+		let start = data[0x102] as usize | ((data[0x103] as usize) << 8);
 
-			// Also add interrupt handlers and the JP instruction
-			let mut stack = vec![start, 0x40, 0x48, 0x50, 0x58, 0x60, 0x100];
-			let mut visited: HashSet<_> = stack.iter().cloned().collect();
+		// Also add interrupt handlers and the JP instruction
+		let mut stack = vec![start, 0x40, 0x48, 0x50, 0x58, 0x60, 0x100];
+		let mut visited: HashSet<_> = stack.iter().cloned().collect();
 
-			while let Some(mut index) = stack.pop() {
-				let mut instruction = INSTRUCTIONS[0];
-				while !instruction.is_ret() && index < data.len() - 2 {
-					visited.insert(index);
+		while let Some(mut index) = stack.pop() {
+			let mut instruction = INSTRUCTIONS[0];
+			while !instruction.is_ret() && index < data.len() - 2 {
+				visited.insert(index);
 
-					instruction = INSTRUCTIONS[data[index] as usize];
-					let step = instruction.operand_length + 1;
+				instruction = INSTRUCTIONS[data[index] as usize];
+				let step = instruction.operand_length + 1;
 
-					let bytes = [data[index], data[index+1], data[index+2]];
-					disassembly = disassembly + &Emulator::disassemble(index as u16, bytes) + "\n";
+				let bytes = [data[index], data[index+1], data[index+2]];
+				disassembly = disassembly + &Emulator::disassemble(index as u16, bytes) + "\n";
 
-					if instruction.is_call() || instruction.is_jump() {
-						let addr = bytes[1] as usize | ((bytes[2] as usize) << 8);
-						if !visited.contains(&addr) {
-							stack.push(addr);
-							// Too many inserts in this function? Probably but meh
-							visited.insert(index);
+				if instruction.is_call() || instruction.is_jump() {
+					let addr = bytes[1] as usize | ((bytes[2] as usize) << 8);
+					if !visited.contains(&addr) {
+						stack.push(addr);
+						// Too many inserts in this function? Probably but meh
+						visited.insert(index);
 
-							if instruction.is_jump() {
-								break;
-							}
+						if instruction.is_jump() {
+							break;
 						}
 					}
-					index += step as usize;
 				}
-				disassembly += "\n";
+				index += step as usize;
 			}
-			disassembly
-		} else {
-			String::new()
+			disassembly += "\n";
 		}
+		disassembly
 	}
 	pub fn get_screen(&self) -> &[[super::Color; 160]; 144] {
 		self.gpu.get_screen()
@@ -327,57 +550,198 @@ impl Emulator {
 	pub fn rb(&self, addr: u16) -> u8 {
 		self.mem.rb(addr)
 	}
+	// Removes and returns the stereo samples (interleaved left/right at SAMPLE_RATE Hz) produced so far
+	pub fn take_audio_samples(&mut self) -> Vec<f32> {
+		self.mem.apu.take_samples()
+	}
+	// Plugs a device into the link port, returning whatever was plugged in before
+	pub fn connect_serial(&mut self, device: Box<SerialDevice>) -> Box<SerialDevice> {
+		self.mem.serial.connect(device)
+	}
+	// Text sent over the link port (see SerialConsole)
+	pub fn serial_output(&self) -> &str {
+		self.mem.serial.console.text()
+	}
+	// Text sent over the link port since the last time this was called
+	pub fn take_serial_output(&mut self) -> String {
+		self.mem.serial.console.take_new()
+	}
+	pub fn get_apu(&self) -> &Apu {
+		&self.mem.apu
+	}
+	pub fn get_apu_mut(&mut self) -> &mut Apu {
+		&mut self.mem.apu
+	}
+	pub fn get_regs(&self) -> &Registers {
+		&self.regs
+	}
+	pub fn disassemble_at(&self, addr: u16) -> String {
+		let get = |addr: u16| self.mem.rb(addr);
+		Emulator::disassemble(addr, [get(addr), get(addr.wrapping_add(1)), get(addr.wrapping_add(2))])
+	}
+	// Disassembles the instruction about to be executed
+	pub fn disassemble_next(&self) -> String {
+		self.disassemble_at(self.regs.pc)
+	}
+
+	// Advances everything besides the CPU
+	// In double speed, only the timers and serial port keep up with the CPU, while the PPU, APU
+	// and cartridge (whose clock runs in real time) see half as many cycles
+	pub(in emulator) fn tick(&mut self, cycles: u64) {
+		let normal_cycles = (cycles/self.get_speed()) as i16;
+		self.gpu.step(&mut self.mem, &self.interrupts, normal_cycles, self.cgb_mode);
+		// DIV doesn't count while a speed switch is settling
+		if self.speed_switch == 0 {
+			self.timers.step(&mut self.mem, &self.interrupts, cycles as i16);
+		}
+		self.mem.cart.step(normal_cycles);
+		self.mem.apu.step(normal_cycles);
+		if self.mem.serial.step(cycles as i16, self.cgb_mode) {
+			self.interrupts.request_interrupt(&mut self.mem, 3);
+		}
+		self.step_cycles += cycles;
+	}
+	// Every memory access the CPU makes takes one machine cycle (4 clock cycles), and the rest of
+	// the machine is ticked before the access happens so it sees up to date PPU/timer/etc. state
+	pub(in emulator) fn read(&mut self, address: u16) -> u8 {
+		self.tick(4);
+		self.mem.rb(address)
+	}
+	pub(in emulator) fn write(&mut self, address: u16, val: u8) {
+		self.tick(4);
+		self.mem.wb(address, val);
+	}
+	pub(in emulator) fn read_word(&mut self, address: u16) -> u16 {
+		let low = self.read(address) as u16;
+		low | (self.read(address.wrapping_add(1)) as u16) << 8
+	}
+	pub(in emulator) fn write_word(&mut self, address: u16, val: u16) {
+		self.write(address, val as u8);
+		self.write(address.wrapping_add(1), (val >> 8) as u8);
+	}
+	// Pushes onto the stack the way the CPU does, high byte first
+	pub(in emulator) fn push(&mut self, val: u16) {
+		self.regs.sp = self.regs.sp.wrapping_sub(1);
+		let sp = self.regs.sp;
+		self.write(sp, (val >> 8) as u8);
+		self.regs.sp = self.regs.sp.wrapping_sub(1);
+		let sp = self.regs.sp;
+		self.write(sp, val as u8);
+	}
+	pub(in emulator) fn pop(&mut self) -> u16 {
+		let sp = self.regs.sp;
+		let val = self.read_word(sp);
+		self.regs.sp = self.regs.sp.wrapping_add(2);
+		val
+	}
+	// Services the highest priority interrupt (if IME is on and one is pending), returning whether it did
+	// This takes 5 machine cycles: 2 doing nothing, 2 pushing PC and 1 jumping to the handler
+	fn dispatch_interrupt(&mut self) -> bool {
+		if !self.interrupts.ime || !self.interrupts.pending(&self.mem) {
+			return false;
+		}
+		self.interrupts.disable();
+		self.tick(8);
 
-	fn emulate_cycle(&mut self, state: &mut ProgramState, dstate: &mut DebugState) -> u64 {
+		let mut pc = self.regs.pc;
+		if self.halt_bug {
+			// The interrupt returns to the HALT itself (which then runs again) rather than the
+			// byte after it, so nothing gets read twice
+			self.halt_bug = false;
+			pc = pc.wrapping_sub(1);
+		}
+		self.regs.sp = self.regs.sp.wrapping_sub(1);
+		let sp = self.regs.sp;
+		self.write(sp, (pc >> 8) as u8);
+		// Which interrupt to service is only decided now, so if that push overwrote IE, the
+		// interrupt can change or be cancelled altogether (in which case PC ends up at 0)
+		let interrupt = self.interrupts.highest_pending(&self.mem);
+		self.regs.sp = self.regs.sp.wrapping_sub(1);
+		let sp = self.regs.sp;
+		self.write(sp, pc as u8);
+
+		self.regs.pc = match interrupt {
+			Some(id) => {
+				self.interrupts.acknowledge(&mut self.mem, id);
+				0x40 + 0x08*id as u16
+			},
+			None => 0
+		};
+		self.tick(4);
+		true
+	}
+	pub(in emulator) fn halt(&mut self) {
+		if !self.interrupts.ime && self.interrupts.pending(&self.mem) {
+			self.halt_bug = true;
+		} else {
+			self.halted = true;
+		}
+	}
+	// Enters low power mode (until a button is pressed)
+	pub(in emulator) fn stop(&mut self) {
+		self.timers.reset_div(&mut self.mem);
+		self.stopped = true;
+	}
+	// Switches a CGB between normal and double speed (KEY1 should have been armed first)
+	pub(in emulator) fn switch_speed(&mut self) {
+		self.timers.reset_div(&mut self.mem);
+		let key1 = self.mem.rb(0xFF4D);
+		self.mem.wb(0xFF4D, key1 & !1);
+		self.mem.switch_speed();
+		self.speed_switch = SPEED_SWITCH_CYCLES;
+	}
+	pub(in emulator) fn read_hl(&mut self) -> u8 {
+		let hl = unsafe {*self.regs.hl()};
+		self.read(hl)
+	}
+	pub(in emulator) fn write_hl(&mut self, val: u8) {
+		let hl = unsafe {*self.regs.hl()};
+		self.write(hl, val);
+	}
+
+	// Runs a single instruction, ticking the rest of the machine as it goes
+	fn emulate_cycle(&mut self) -> Option<Event> {
 		let address = self.regs.pc;
-		let opcode = self.mem.rb(self.regs.pc); self.regs.pc += 1;
+		let opcode = self.read(address);
+		if self.halt_bug {
+			// PC fails to move past the opcode, so it gets read again as the next byte
+			self.halt_bug = false;
+		} else {
+			self.regs.pc += 1;
+		}
 		let instruction = INSTRUCTIONS[opcode as usize];
 
-		let operand = if instruction.operand_length == 1 {
-			self.mem.rb(self.regs.pc) as u16
-		} else {
-			self.mem.rw(self.regs.pc)
+		let pc = self.regs.pc;
+		let operand = match instruction.operand_length {
+			0 => 0,
+			1 => self.read(pc) as u16,
+			_ => self.read_word(pc)
 		};
 		self.regs.pc += instruction.operand_length;
 
+		let mut event = None;
 		if opcode == 0x20 && operand == 0xFE && !self.regs.get_flag(ZERO_FLAG) {
 			// jump back 2 bytes if zero flag not set
 			// program counter will return to pointing to this instruction and then repeat
-			println!("Error: Emulation caught in infinite loop");
-			if self.inf_loop_breakpoint {
-				state.paused = true;
-			}
-		}
-
-		if state.debug {
-			// TODO: always store debug info but do so without slowing everything down
-			if state.debug_regs {
-				dstate.add_text(&format!("{:?}\n", self.regs), 2);
-			}
-
-			// Gotta love shorter names
-			let disassembly = {
-				let (get, addr) = (|addr| self.mem.rb(addr), address);
-				Emulator::disassemble(addr, [get(addr), get(addr+1), get(addr+2)])
-			};
-			dstate.add_text(&format!("{}\n", disassembly), 1);
+			event = Some(Event::InfiniteLoop(address));
 		}
 
 		let cycles: u64;
 		if let Some(func) = instruction.func {
 			cycles = func(self, operand);
 		} else {
-			println!("\nUnimplemented instruction at memory address ({:#X}) [{:#X} ({} | {})] called with operand {:#X}\n", 
-				address, opcode, instruction.name, instruction.operand_length, operand);
-			if self.unimpl_instr_breakpoint {
-				state.paused = true;
-			}
 			// Feels weird to keep emulator running, but panicing destorys potentially useful debug info
+			event = Some(Event::UnimplementedInstruction(address, opcode));
 			cycles = 4;
 		}
 		
-		self.clock += cycles;
-		cycles
+		// Whatever the instruction spent on anything besides memory accesses
+		if cycles > self.step_cycles {
+			let remaining = cycles - self.step_cycles;
+			self.tick(remaining);
+		}
+		self.clock += self.step_cycles;
+		event
 	}
 	fn disassemble(address: u16, bytes: [u8; 3]) -> String {
 		const OP_TYPES: [&'static str; 5] = ["d16", "a8", "a16", "r8", "d8"];
@@ -404,3 +768,569 @@ impl Emulator {
 		format!("{:#X}:\t{:#X} {} {} \t{}", address, opcode, disassemble_op(0), disassemble_op(1), disassembly)
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::rc::Rc;
+	use std::cell::RefCell;
+	use std::env;
+	use std::fs;
+	use emulator::Mapper;
+
+	// Maps every address to the last value written anywhere
+	struct LatchMapper(u8);
+
+	impl Mapper for LatchMapper {
+		fn rb(&self, _: usize) -> u8 {
+			self.0
+		}
+		fn wb(&mut self, _: usize, val: u8) {
+			self.0 = val;
+		}
+		fn load_game(&mut self, game: &[u8]) -> usize {
+			game.len()
+		}
+		fn save_state(&self, out: &mut Write) -> io::Result<()> {
+			out.write_all(&[self.0])
+		}
+		fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+			let mut buf = [0];
+			inp.read_exact(&mut buf)?;
+			self.0 = buf[0];
+			Ok(())
+		}
+	}
+
+	fn new_latch_mapper(_: usize, _: usize) -> Box<Mapper> {
+		Box::new(LatchMapper(0xAB))
+	}
+
+	fn make_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+		let mut rom = vec![0; 0x8000 << rom_size];
+		rom[0x147] = cartridge_type;
+		rom[0x148] = rom_size;
+		rom[0x149] = ram_size;
+		rom
+	}
+
+	#[test]
+	fn test_load_errors() {
+		let mut emu = Emulator::default();
+		match emu.load_game_from_bytes(&[0; 0x100]) {
+			Err(LoadError::TruncatedRom{expected: 0x150, actual: 0x100}) => {},
+			res => panic!("Expected truncated header, got {:?}", res)
+		}
+		match emu.load_game_from_bytes(&make_rom(0x20, 0, 0)) {
+			Err(LoadError::UnsupportedMapper(0x20)) => {},
+			res => panic!("Expected unsupported mapper, got {:?}", res)
+		}
+		let mut rom = make_rom(0x01, 0, 0);
+		rom[0x148] = 0x52;
+		match emu.load_game_from_bytes(&rom) {
+			Err(LoadError::BadHeader(_)) => {},
+			res => panic!("Expected bad header, got {:?}", res)
+		}
+		let mut rom = make_rom(0x01, 1, 0);
+		rom.truncate(0x8000);
+		match emu.load_game_from_bytes(&rom) {
+			Err(LoadError::TruncatedRom{expected: 0x10000, actual: 0x8000}) => {},
+			res => panic!("Expected truncated ROM, got {:?}", res)
+		}
+	}
+	#[test]
+	fn test_custom_mapper() {
+		let mut emu = Emulator::default();
+		emu.register_mapper(0xFC, new_latch_mapper);
+		emu.load_game_from_bytes(&make_rom(0xFC, 0, 0)).unwrap();
+		assert_eq!(emu.rb(0x4000), 0xAB);
+		emu.mem.wb(0x2000, 0x12);
+		assert_eq!(emu.rb(0xA000), 0x12);
+	}
+	#[test]
+	fn test_rom_bank_wrap_around() {
+		// 64KB ROM (4 banks) where every byte holds its bank number
+		let mut rom = make_rom(0x01, 1, 0);
+		for (i, byte) in rom.iter_mut().enumerate().skip(0x150) {
+			*byte = (i/0x4000) as u8;
+		}
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.mem.wb(0x2000, 3);
+		assert_eq!(emu.rb(0x4000), 3);
+		emu.mem.wb(0x2000, 6);
+		assert_eq!(emu.rb(0x4000), 2);
+		assert_eq!(emu.export_sram().len(), 0);
+	}
+	#[test]
+	fn test_sram_round_trip() {
+		let rom = make_rom(0x03, 0, 2);
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.mem.wb(0x0000, 0x0A);
+		emu.mem.wb(0xA123, 0x42);
+
+		let sram = emu.export_sram();
+		assert_eq!(sram.len(), 8*1024);
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.load_sram(&mut &sram[..]).unwrap();
+		emu.mem.wb(0x0000, 0x0A);
+		assert_eq!(emu.rb(0xA123), 0x42);
+	}
+	#[test]
+	fn test_save_state_round_trip() {
+		let mut rom = make_rom(0x03, 0, 2);
+		// Turn on the LCD and sound, then count up in RAM forever
+		let code = [0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x80, 0xE0, 0x26, 0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD];
+		rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.skip_bios();
+		emu.run_frame();
+
+		let state = emu.export_state();
+		for _ in 0..3 {
+			emu.run_frame();
+		}
+		let after = emu.export_state();
+		let screen = emu.get_screen().to_vec();
+
+		emu.import_state(&state).unwrap();
+		assert_eq!(emu.export_state(), state);
+		for _ in 0..3 {
+			emu.run_frame();
+		}
+		assert_eq!(emu.export_state(), after);
+		assert_eq!(emu.get_screen().to_vec(), screen);
+	}
+	#[test]
+	fn test_bad_save_states() {
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&make_rom(0x03, 0, 2)).unwrap();
+		let state = emu.export_state();
+
+		match emu.import_state(b"RGBX") {
+			Err(LoadError::BadSaveState(_)) => {},
+			other => panic!("Unexpected result: {:?}", other)
+		}
+
+		// Loading half a state fails without changing anything
+		emu.mem.wb(0xC000, 0x42);
+		let changed = emu.export_state();
+		assert!(emu.import_state(&state[..state.len()/2]).is_err());
+		assert_eq!(emu.export_state(), changed);
+
+		let mut other_game = make_rom(0x03, 0, 2);
+		other_game[0x134] = b'X';
+		let mut other = Emulator::default();
+		other.load_game_from_bytes(&other_game).unwrap();
+		match other.import_state(&state) {
+			Err(LoadError::BadSaveState(_)) => {},
+			other => panic!("Unexpected result: {:?}", other)
+		}
+
+		// A speed switch that would never finish is rejected
+		let offset = STATE_MAGIC.len() + 4 + 4 + emu.game_id().len() + 8 + 4;
+		for &cycles in &[SPEED_SWITCH_CYCLES + 4, 6] {
+			let mut bad = state.clone();
+			bad[offset..offset + 8].copy_from_slice(&[cycles as u8, (cycles >> 8) as u8, 0, 0, 0, 0, 0, 0]);
+			match emu.import_state(&bad) {
+				Err(LoadError::BadSaveState(_)) => {},
+				other => panic!("Unexpected result: {:?}", other)
+			}
+			assert_eq!(emu.export_state(), changed);
+		}
+	}
+	#[test]
+	fn test_bios_finished() {
+		// A BIOS that jumps straight to the game, which jumps back to 0x100 forever
+		let mut bios = vec![0; 0x100];
+		bios[..3].copy_from_slice(&[0xC3, 0x00, 0x01]);
+		let mut rom = make_rom(0x00, 0, 0);
+		rom[0x100..0x103].copy_from_slice(&[0xC3, 0x00, 0x01]);
+		let mut emu = Emulator::default();
+		emu.load_bios_from_bytes(&bios).unwrap();
+		emu.load_game_from_bytes(&rom).unwrap();
+
+		assert_eq!(emu.step().1, Some(Event::BiosFinished));
+		for _ in 0..10 {
+			assert_eq!(emu.step().1, None);
+		}
+	}
+	#[test]
+	fn test_unreadable_save() {
+		let dir = env::temp_dir().join(format!("rgb_save_test_{}", ::std::process::id()));
+		let _ = fs::create_dir_all(dir.join("game.rsav"));
+		let game = dir.join("game.gb");
+		File::create(&game).unwrap().write_all(&make_rom(0x03, 0, 2)).unwrap();
+
+		// The save is a directory, so it can't be read, but the game still loads
+		let mut emu = Emulator::default();
+		match emu.load_game(game.to_str().unwrap().to_string()) {
+			Err(LoadError::BadSave(_)) => {},
+			other => panic!("Unexpected result: {:?}", other)
+		}
+		assert!(emu.rom_hash() != 0);
+		assert_eq!(emu.save_game(), 0);
+		let _ = fs::remove_dir_all(&dir);
+	}
+	#[test]
+	fn test_rom_only_writes() {
+		let mut rom = make_rom(0x00, 0, 0);
+		rom[0x2000] = 0x42;
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&rom).unwrap();
+
+		// Games without a mapper still "select a bank" sometimes, which mustn't change the ROM
+		emu.mem.wb(0x2000, 0x01);
+		assert_eq!(emu.mem.rb(0x2000), 0x42);
+	}
+	// ROM followed by RAM, which remembers when (in cycles) each access happened
+	struct TimingMapper {
+		mem:		Vec<u8>,
+		cycles:		u64,
+		accesses:	Rc<RefCell<Vec<(u64, usize)>>>
+	}
+
+	impl Mapper for TimingMapper {
+		fn rb(&self, address: usize) -> u8 {
+			self.accesses.borrow_mut().push((self.cycles, address));
+			self.mem[address]
+		}
+		fn wb(&mut self, address: usize, val: u8) {
+			self.accesses.borrow_mut().push((self.cycles, address));
+			if address >= 0xA000 {
+				self.mem[address] = val;
+			}
+		}
+		fn load_game(&mut self, game: &[u8]) -> usize {
+			game.len()
+		}
+		fn step(&mut self, cycles: i16) {
+			self.cycles += cycles as u64;
+		}
+		fn save_state(&self, _: &mut Write) -> io::Result<()> {
+			Ok(())
+		}
+		fn load_state(&mut self, _: &mut Read) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_memory_access_timing() {
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&make_rom(0x00, 0, 0)).unwrap();
+		emu.skip_bios();
+		let mut mem = vec![0; 0xC000];
+		// CALL 0x0200
+		mem[0x100..0x103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+		let accesses = Rc::new(RefCell::new(Vec::new()));
+		emu.mem.cart = Box::new(TimingMapper{mem: mem, cycles: 0, accesses: accesses.clone()});
+		emu.regs.sp = 0xA010;
+		assert_eq!(emu.step().0, 24);
+		assert_eq!(emu.regs.pc, 0x200);
+
+		// The rest of the machine is ticked before each access, and there is a delay before the pushes
+		assert_eq!(*accesses.borrow(), vec![(4, 0x100), (8, 0x101), (12, 0x102), (20, 0xA00F), (24, 0xA00E)]);
+		assert_eq!(emu.rb(0xA00F), 0x01);
+		assert_eq!(emu.rb(0xA00E), 0x03);
+	}
+
+	// Machine cycles each instruction takes (from Blargg's instr_timing), with branches not taken
+	// 0 means there's no instruction (or that it isn't timed here)
+	const INSTRUCTION_TIMES: [u8; 256] = [
+		1,3,2,2,1,1,2,1,5,2,2,2,1,1,2,1,
+		0,3,2,2,1,1,2,1,3,2,2,2,1,1,2,1,
+		2,3,2,2,1,1,2,1,2,2,2,2,1,1,2,1,
+		2,3,2,2,3,3,3,1,2,2,2,2,1,1,2,1,
+		1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+		1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+		1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+		2,2,2,2,2,2,0,2,1,1,1,1,1,1,2,1,
+		1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+		1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+		1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+		1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+		2,3,3,4,3,4,2,4,2,4,3,0,3,6,2,4,
+		2,3,3,0,3,4,2,4,2,4,3,0,3,0,2,4,
+		3,3,2,0,0,4,2,4,4,1,4,0,0,0,2,4,
+		3,3,2,1,0,4,2,4,3,2,4,1,0,0,2,4
+	];
+
+	// Gets ready to run code from RAM (at 0xC000) with interrupts off
+	fn run_from_ram(code: &[u8]) -> Emulator {
+		let mut emu = Emulator::default();
+		emu.load_game_from_bytes(&make_rom(0x00, 0, 0)).unwrap();
+		emu.skip_bios();
+		for (i, &byte) in code.iter().enumerate() {
+			emu.mem.wb(0xC000 + i as u16, byte);
+		}
+		emu.regs.pc = 0xC000;
+		emu.regs.sp = 0xDFF0;
+		unsafe {*emu.regs.hl() = 0xC100;}
+		emu.interrupts.ime = false;
+		emu.mem.wb(0xFFFF, 0);
+		emu.mem.wb(0xFF0F, 0);
+		emu
+	}
+
+	fn time_instruction(bytes: &[u8], flags: u8) -> u64 {
+		let mut emu = run_from_ram(bytes);
+		*emu.regs.f() = flags;
+		emu.step().0
+	}
+
+	// HALT; INC A; INC A; ...
+	const HALT_CODE: [u8; 5] = [0x76, 0x3C, 0x3C, 0x3C, 0x3C];
+	const TIMER_INTERRUPT: u8 = 1 << 2;
+
+	#[test]
+	fn test_halt_with_ime() {
+		let mut emu = run_from_ram(&HALT_CODE);
+		emu.interrupts.ime = true;
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.step();
+		for _ in 0..10 {
+			assert!(emu.halted);
+			assert_eq!(emu.step().0, 4);
+		}
+
+		// The interrupt wakes the CPU up and is serviced, returning to just after HALT
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		emu.step();
+		assert!(!emu.halted);
+		assert_eq!(emu.regs.pc, 0x50);
+		assert_eq!(emu.mem.rw(emu.regs.sp), 0xC001);
+		assert_eq!(emu.mem.rb(0xFF0F) & TIMER_INTERRUPT, 0);
+	}
+	#[test]
+	fn test_halt_with_ime_and_pending_interrupt() {
+		let mut emu = run_from_ram(&HALT_CODE);
+		emu.interrupts.ime = true;
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		emu.step();
+		assert!(!emu.halted);
+		assert_eq!(emu.regs.pc, 0x50);
+		assert_eq!(emu.mem.rw(emu.regs.sp), 0xC001);
+	}
+	#[test]
+	fn test_halt_without_ime() {
+		let mut emu = run_from_ram(&HALT_CODE);
+		*emu.regs.a() = 0;
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		// Requested but not enabled interrupts don't wake the CPU
+		emu.mem.wb(0xFF0F, 1 << 4);
+		emu.step();
+		for _ in 0..10 {
+			assert!(emu.halted);
+			emu.step();
+		}
+
+		// Once an enabled one is requested, the CPU carries on without servicing it
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		emu.step();
+		assert!(!emu.halted);
+		assert_eq!(emu.regs.pc, 0xC001);
+		emu.step();
+		assert_eq!((*emu.regs.a(), emu.regs.pc), (1, 0xC002));
+		assert_eq!(emu.mem.rb(0xFF0F) & TIMER_INTERRUPT, TIMER_INTERRUPT);
+	}
+	#[test]
+	fn test_halt_bug() {
+		let mut emu = run_from_ram(&HALT_CODE);
+		*emu.regs.a() = 0;
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		emu.step();
+		assert!(!emu.halted);
+		assert_eq!(emu.regs.pc, 0xC001);
+
+		// The INC A after HALT runs twice
+		emu.step();
+		assert_eq!((*emu.regs.a(), emu.regs.pc), (1, 0xC001));
+		emu.step();
+		assert_eq!((*emu.regs.a(), emu.regs.pc), (2, 0xC002));
+		emu.step();
+		assert_eq!((*emu.regs.a(), emu.regs.pc), (3, 0xC003));
+	}
+
+	#[test]
+	fn test_instruction_timing() {
+		let mut wrong = Vec::new();
+		for opcode in 0..256 {
+			let expected = INSTRUCTION_TIMES[opcode] as u64*4;
+			if expected == 0 {
+				continue;
+			}
+			// Every condition is taken with one set of flags or the other
+			let taken = match opcode {
+				0x20 | 0x28 | 0x30 | 0x38 => 12,
+				0xC0 | 0xC8 | 0xD0 | 0xD8 => 20,
+				0xC2 | 0xCA | 0xD2 | 0xDA => 16,
+				0xC4 | 0xCC | 0xD4 | 0xDC => 24,
+				_ => expected
+			};
+			let bytes = [opcode as u8, 0x00, 0xC1];
+			let times = (time_instruction(&bytes, 0xF0), time_instruction(&bytes, 0x00));
+			if (times.0.min(times.1), times.0.max(times.1)) != (expected, taken) {
+				wrong.push(format!("{:02X}: {:?} (expected {} or {})", opcode, times, expected, taken));
+			}
+		}
+		for opcode in 0..256 {
+			// Only the (HL) versions take more than 2 machine cycles, and BIT doesn't write back
+			let expected = match (opcode & 0x07, opcode & 0xC0) {
+				(6, 0x40) => 12,
+				(6, _) => 16,
+				_ => 8
+			};
+			let time = time_instruction(&[0xCB, opcode as u8], 0);
+			if time != expected {
+				wrong.push(format!("CB {:02X}: {} (expected {})", opcode, time, expected));
+			}
+		}
+		assert!(wrong.is_empty(), "Wrong timings:\n{}", wrong.join("\n"));
+	}
+	#[test]
+	fn test_ei_delay() {
+		// EI; INC A; INC A
+		let mut emu = run_from_ram(&[0xFB, 0x3C, 0x3C]);
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		assert_eq!(emu.step().0, 4);
+		assert_eq!(emu.regs.pc, 0xC001);
+
+		// The interrupt is serviced after the instruction following EI, which takes 20 cycles
+		assert_eq!(emu.step().0, 4 + 20);
+		assert_eq!(emu.regs.pc, 0x50);
+		assert_eq!(emu.mem.rw(emu.regs.sp), 0xC002);
+		assert!(!emu.interrupts.ime);
+	}
+	#[test]
+	fn test_ei_halt() {
+		// EI; HALT; INC A with an interrupt already pending
+		let mut emu = run_from_ram(&[0xFB, 0x76, 0x3C]);
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		emu.step();
+
+		// IME is still off during HALT, so the HALT bug kicks in, but the interrupt returns to the HALT
+		emu.step();
+		assert_eq!(emu.regs.pc, 0x50);
+		assert_eq!(emu.mem.rw(emu.regs.sp), 0xC001);
+		assert!(!emu.halt_bug && !emu.halted);
+
+		// So the handler's first instruction only runs once
+		emu.step();
+		assert_eq!(emu.regs.pc, 0x51);
+	}
+	#[test]
+	fn test_di_cancels_ei() {
+		// EI; DI; NOP
+		let mut emu = run_from_ram(&[0xFB, 0xF3, 0x00]);
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		for pc in 0xC001..0xC004 {
+			emu.step();
+			assert_eq!(emu.regs.pc, pc);
+		}
+		assert!(!emu.interrupts.ime);
+	}
+	#[test]
+	fn test_interrupt_priority() {
+		let mut emu = run_from_ram(&[0x00]);
+		emu.interrupts.ime = true;
+		emu.mem.wb(0xFFFF, 0x1F);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT | 1 << 4 | 1 << 1);
+		emu.step();
+		assert_eq!(emu.regs.pc, 0x48);
+		assert_eq!(emu.mem.rb(0xFF0F) & 0x1F, TIMER_INTERRUPT | 1 << 4);
+	}
+	#[test]
+	fn test_interrupt_cancelled_by_push() {
+		// With SP at 0, pushing PC's high byte (0xC0) overwrites IE, so nothing is enabled any more
+		let mut emu = run_from_ram(&[0x00]);
+		emu.interrupts.ime = true;
+		emu.regs.sp = 0x0000;
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT);
+		assert_eq!(emu.step().0, 4 + 20);
+		assert_eq!(emu.regs.pc, 0x0000);
+		assert_eq!(emu.mem.rb(0xFFFF), 0xC0);
+		assert_eq!(emu.mem.rb(0xFF0F) & TIMER_INTERRUPT, TIMER_INTERRUPT);
+		assert!(!emu.interrupts.ime);
+
+		// But if the pushed byte (0xC1 here) still enables something, that gets serviced instead
+		let mut emu = run_from_ram(&[0x00]);
+		emu.interrupts.ime = true;
+		emu.regs.pc = 0xC100;
+		emu.mem.wb(0xC100, 0x00);
+		emu.regs.sp = 0x0000;
+		emu.mem.wb(0xFFFF, TIMER_INTERRUPT);
+		emu.mem.wb(0xFF0F, TIMER_INTERRUPT | 1);
+		emu.step();
+		assert_eq!(emu.regs.pc, 0x40);
+		assert_eq!(emu.mem.rb(0xFF0F) & 0x1F, TIMER_INTERRUPT);
+	}
+	#[test]
+	fn test_stop() {
+		// STOP; INC A
+		let mut emu = run_from_ram(&[0x10, 0x00, 0x3C]);
+		emu.mem.incr_div();
+		emu.step();
+		assert!(emu.is_stopped());
+		assert_eq!((emu.regs.pc, emu.mem.rb(0xFF04)), (0xC002, 0));
+
+		// Nothing happens until a button is pressed, not even the timers
+		emu.run_frame();
+		assert_eq!((emu.regs.pc, emu.mem.rb(0xFF04)), (0xC002, 0));
+		emu.set_joypad(0x80);
+		assert!(!emu.is_stopped());
+		emu.step();
+		assert_eq!(emu.regs.pc, 0xC003);
+	}
+	#[test]
+	fn test_speed_switch() {
+		// STOP, followed by NOPs
+		let mut emu = run_from_ram(&[0x10, 0x00]);
+		emu.cgb_mode = true;
+		emu.mem.cgb_mode = true;
+		emu.mem.wb(0xFF4D, 0x01);
+		emu.step();
+		assert!(!emu.is_stopped());
+		assert_eq!((emu.get_speed(), emu.mem.rb(0xFF4D) & 0x81), (2, 0x80));
+
+		// The CPU and DIV are paused for a while
+		let mut cycles = 0;
+		while emu.regs.pc == 0xC002 {
+			cycles += emu.step().0;
+			assert_eq!(emu.mem.rb(0xFF04), 0);
+		}
+		assert_eq!(cycles, SPEED_SWITCH_CYCLES + 4);
+
+		// The PPU still draws a line every 456 normal speed cycles
+		let line_cycles = |emu: &mut Emulator| {
+			let line = emu.mem.rb(0xFF44);
+			let mut cycles = 0;
+			while emu.mem.rb(0xFF44) == line {
+				cycles += emu.step().0;
+			}
+			cycles
+		};
+		line_cycles(&mut emu);
+		assert_eq!(line_cycles(&mut emu), 2*456);
+
+		// And switching back works the same way
+		emu.mem.wb(0xFF4D, 0x01);
+		emu.regs.pc = 0xC000;
+		emu.step();
+		assert_eq!((emu.get_speed(), emu.mem.rb(0xFF4D) & 0x81), (1, 0));
+		while emu.regs.pc == 0xC002 {
+			emu.step();
+		}
+		line_cycles(&mut emu);
+		assert_eq!(line_cycles(&mut emu), 456);
+	}
+}
@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// Everything that can go wrong while loading a game, BIOS or save state
+#[derive(Debug)]
+pub enum LoadError {
+	// The file could not be opened or read
+	Io(io::Error),
+	// The cartridge type (header byte 0x147) is unknown or not implemented
+	UnsupportedMapper(u8),
+	// Some other part of the cartridge header is invalid
+	BadHeader(String),
+	// The file is smaller than its header claims it should be
+	TruncatedRom{expected: usize, actual: usize},
+	// The save state is from a different version of the emulator or a different game
+	BadSaveState(String),
+	// The game itself loaded fine, but its .rsav save file couldn't be read
+	// The game runs without it, and won't save over it either
+	BadSave(io::Error)
+}
+
+impl fmt::Display for LoadError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			LoadError::Io(ref err) => write!(f, "I/O error: {}", err),
+			LoadError::UnsupportedMapper(code) => write!(f, "Unsupported cartridge type: {:#X}", code),
+			LoadError::BadHeader(ref msg) => write!(f, "Bad cartridge header: {}", msg),
+			LoadError::TruncatedRom{expected, actual} => 
+				write!(f, "ROM is truncated: expected {} bytes but found {}", expected, actual),
+			LoadError::BadSaveState(ref msg) => write!(f, "Bad save state: {}", msg),
+			LoadError::BadSave(ref err) => write!(f, "Could not read save file: {}", err)
+		}
+	}
+}
+
+impl Error for LoadError {
+	fn description(&self) -> &str {
+		match *self {
+			LoadError::Io(_) => "I/O error",
+			LoadError::UnsupportedMapper(_) => "unsupported cartridge type",
+			LoadError::BadHeader(_) => "bad cartridge header",
+			LoadError::TruncatedRom{..} => "truncated ROM",
+			LoadError::BadSaveState(_) => "bad save state",
+			LoadError::BadSave(_) => "unreadable save file"
+		}
+	}
+}
+
+impl From<io::Error> for LoadError {
+	fn from(err: io::Error) -> LoadError {
+		LoadError::Io(err)
+	}
+}
+
+// Everything that can go wrong while playing back an input movie
+#[derive(Debug)]
+pub enum MovieError {
+	// The file could not be read or written
+	Io(io::Error),
+	// The file isn't a movie this version of the emulator understands
+	BadMovie(String),
+	// The movie was recorded with a different ROM
+	WrongRom,
+	// The emulator's state stopped matching the movie at the given frame
+	Desync(usize)
+}
+
+impl fmt::Display for MovieError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MovieError::Io(ref err) => write!(f, "I/O error: {}", err),
+			MovieError::BadMovie(ref msg) => write!(f, "Bad movie: {}", msg),
+			MovieError::WrongRom => write!(f, "Movie was recorded with a different ROM"),
+			MovieError::Desync(frame) => write!(f, "Movie desynced at frame {}", frame)
+		}
+	}
+}
+
+impl Error for MovieError {
+	fn description(&self) -> &str {
+		match *self {
+			MovieError::Io(_) => "I/O error",
+			MovieError::BadMovie(_) => "bad movie",
+			MovieError::WrongRom => "wrong ROM",
+			MovieError::Desync(_) => "movie desynced"
+		}
+	}
+}
+
+impl From<io::Error> for MovieError {
+	fn from(err: io::Error) -> MovieError {
+		MovieError::Io(err)
+	}
+}
+
+impl From<LoadError> for MovieError {
+	fn from(err: LoadError) -> MovieError {
+		match err {
+			LoadError::Io(err) => MovieError::Io(err),
+			other => MovieError::BadMovie(other.to_string())
+		}
+	}
+}
@@ -1,5 +1,9 @@
+use std::io;
+use std::io::{Write, Read};
+
 use emulator::Memory;
 use emulator::InterruptManager;
+use emulator::serialize::*;
 
 const SCANLINE_TOTAL_TIME: i16 = 456;
 const SCANLINE_MODE2_OVER: i16 = 456-80;
@@ -87,6 +91,45 @@ impl Gpu {
 	pub fn get_scanline_count(&self) -> i16 {
 		self.sl_count
 	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		// Every color is stored as a tag (0-3 for the monochrome shades, 4 for CGB) followed by RGB
+		let mut screen = Vec::with_capacity(4*160*144);
+		for &color in self.screen_data.iter().flat_map(|row| row.iter()) {
+			screen.extend_from_slice(&match color {
+				Color::WHITE => [0, 0, 0, 0],
+				Color::LIGHT_GRAY => [1, 0, 0, 0],
+				Color::DARK_GRAY => [2, 0, 0, 0],
+				Color::BLACK => [3, 0, 0, 0],
+				Color::CGB(red, green, blue) => [4, red, green, blue]
+			});
+		}
+		write_bytes(out, &screen)?;
+
+		let priority = self.bg_priority.iter().flat_map(|row| row.iter().cloned()).collect::<Vec<_>>();
+		write_bytes(out, &priority)?;
+		write_i16(out, self.sl_count)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		let mut screen = vec![0; 4*160*144];
+		read_bytes(inp, &mut screen)?;
+		for (i, color) in screen.chunks(4).enumerate() {
+			self.screen_data[i/160][i%160] = match color[0] {
+				0 => Color::WHITE,
+				1 => Color::LIGHT_GRAY,
+				2 => Color::DARK_GRAY,
+				3 => Color::BLACK,
+				_ => Color::CGB(color[1], color[2], color[3])
+			};
+		}
+
+		let mut priority = vec![0; 160*144];
+		read_bytes(inp, &mut priority)?;
+		for (i, &p) in priority.iter().enumerate() {
+			self.bg_priority[i/160][i%160] = p;
+		}
+		self.sl_count = read_i16(inp)?;
+		Ok(())
+	}
 	pub fn step(&mut self, mem: &mut Memory, im: &InterruptManager, cycles: i16, cgb_mode: bool) {
 		self.set_lcd_status(mem, im);
 		if self.is_lcd_enabled(mem) {
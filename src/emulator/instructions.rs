@@ -22,13 +22,11 @@ macro_rules! xor {
 
 	(hl) => {
     	|emu, _| {
-	    	unsafe {
-	    		let (a,b) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()));
-	    		*emu.regs.a() ^= b;
-	    		emu.regs.update_flags(ZERO_FLAG, (a ^ b) == 0);
-	    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG | CARRY_FLAG);
-	    		8
-	    	}
+    		let (a,b) = (*emu.regs.a(), emu.read_hl());
+    		*emu.regs.a() ^= b;
+    		emu.regs.update_flags(ZERO_FLAG, (a ^ b) == 0);
+    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG | CARRY_FLAG);
+    		8
     	}
     };
 
@@ -67,14 +65,16 @@ macro_rules! ld {
 
     (c, mem, $reg:ident) => {
     	|emu, _| {
-    		emu.mem.wb(0xFF00 + *emu.regs.c() as u16, *emu.regs.$reg());
+    		let (address, val) = (0xFF00 + *emu.regs.c() as u16, *emu.regs.$reg());
+    		emu.write(address, val);
     		8
     	}
     };
 
     ($reg:ident, c, mem) => {
     	|emu, _| {
-    		*emu.regs.$reg() = emu.mem.rb(0xFF00 + *emu.regs.c() as u16);
+    		let address = 0xFF00 + *emu.regs.c() as u16;
+    		*emu.regs.$reg() = emu.read(address);
     		8
     	}
     };
@@ -82,8 +82,9 @@ macro_rules! ld {
     ($reg1:ident, mem, $reg2:ident, $shift:expr) => {
     	|emu, _| {
     		unsafe {
-    			emu.mem.wb(*emu.regs.$reg1(), *emu.regs.$reg2());
-    			*emu.regs.$reg1() = (*emu.regs.$reg1() as i32 + $shift) as u16;
+    			let (address, val) = (*emu.regs.$reg1(), *emu.regs.$reg2());
+    			emu.write(address, val);
+    			*emu.regs.$reg1() = (address as i32 + $shift) as u16;
     		}
     		8
     	}
@@ -92,8 +93,9 @@ macro_rules! ld {
     ($reg1:ident, $reg2:ident, mem, $shift:expr) => {
     	|emu, _| {
     		unsafe {
-    			*emu.regs.$reg1() = emu.mem.rb(*emu.regs.$reg2());
-    			*emu.regs.$reg2() = (*emu.regs.$reg2() as i32 + $shift) as u16;
+    			let address = *emu.regs.$reg2();
+    			*emu.regs.$reg1() = emu.read(address);
+    			*emu.regs.$reg2() = (address as i32 + $shift) as u16;
     		}
     		8
     	}
@@ -110,9 +112,10 @@ macro_rules! ld {
 macro_rules! rst {
     ($val:expr) => {
     	|emu, _| {
-    		emu.mem.ww(emu.regs.sp-2, emu.regs.pc);
+    		let pc = emu.regs.pc;
+    		emu.tick(4);
+    		emu.push(pc);
     		emu.regs.pc = $val;
-    		emu.regs.sp -= 2;
     		16
     	}
     }
@@ -150,8 +153,9 @@ macro_rules! inc {
     ($reg:ident, mem) => {
     	|emu, _| {
     		unsafe {
-    			let val = emu.mem.rb(*emu.regs.$reg()).wrapping_add(1);
-    			emu.mem.wb(*emu.regs.$reg(), val);
+    			let address = *emu.regs.$reg();
+    			let val = emu.read(address).wrapping_add(1);
+    			emu.write(address, val);
 
     			emu.regs.update_flags(ZERO_FLAG, val == 0);
 	    		emu.regs.clear_flags(NEGATIVE_FLAG);
@@ -194,8 +198,9 @@ macro_rules! dec {
     ($reg:ident, mem) => {
     	|emu, _| {
     		unsafe {
-    			let val = emu.mem.rb(*emu.regs.$reg()).wrapping_sub(1);
-    			emu.mem.wb(*emu.regs.$reg(), val);
+    			let address = *emu.regs.$reg();
+    			let val = emu.read(address).wrapping_sub(1);
+    			emu.write(address, val);
 
     			emu.regs.update_flags(ZERO_FLAG, val == 0);
 	    		emu.regs.set_flags(NEGATIVE_FLAG);
@@ -210,8 +215,9 @@ macro_rules! push {
     ($reg:ident) => {
     	|emu, _| {
     		unsafe {
-    			emu.mem.ww(emu.regs.sp-2, *emu.regs.$reg());
-    			emu.regs.sp -= 2;
+    			let val = *emu.regs.$reg();
+    			emu.tick(4);
+    			emu.push(val);
     			16
     		}
     	}
@@ -222,8 +228,7 @@ macro_rules! pop {
     ($reg:ident) => {
     	|emu, _| {
     		unsafe {
-    			*emu.regs.$reg() = emu.mem.rw(emu.regs.sp);
-    			emu.regs.sp += 2;
+    			*emu.regs.$reg() = emu.pop();
     			12
     		}
     	}
@@ -244,13 +249,11 @@ macro_rules! cp {
 
 	(hl) => {
 		|emu, _| {
-			unsafe {
-				let (a,b) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()));
-		    	emu.regs.update_flags(ZERO_FLAG, a == b);
-				emu.regs.set_flags(NEGATIVE_FLAG);
-				emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) < (b & 0xF));
-				emu.regs.update_flags(CARRY_FLAG, a < b);
-			}
+			let (a,b) = (*emu.regs.a(), emu.read_hl());
+	    	emu.regs.update_flags(ZERO_FLAG, a == b);
+			emu.regs.set_flags(NEGATIVE_FLAG);
+			emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) < (b & 0xF));
+			emu.regs.update_flags(CARRY_FLAG, a < b);
 			8
 		}
 	};
@@ -282,15 +285,13 @@ macro_rules! sub {
 
 	(hl) => {
     	|emu, _| {
-    		unsafe {
-    			let (a,b) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()));
-	    		*emu.regs.a() = (*emu.regs.a()).wrapping_sub(b);
-	    		emu.regs.update_flags(ZERO_FLAG, a == b);
-				emu.regs.set_flags(NEGATIVE_FLAG);
-				emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) < (b & 0xF));
-				emu.regs.update_flags(CARRY_FLAG, a < b);
-	    		8
-    		}
+			let (a,b) = (*emu.regs.a(), emu.read_hl());
+    		*emu.regs.a() = (*emu.regs.a()).wrapping_sub(b);
+    		emu.regs.update_flags(ZERO_FLAG, a == b);
+			emu.regs.set_flags(NEGATIVE_FLAG);
+			emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) < (b & 0xF));
+			emu.regs.update_flags(CARRY_FLAG, a < b);
+    		8
     	}
     };
 
@@ -321,14 +322,12 @@ macro_rules! and {
 
 	(hl) => {
     	|emu, _| {
-	    	unsafe {
-	    		let (a,b) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()));
-	    		*emu.regs.a() &= b;
-	    		emu.regs.update_flags(ZERO_FLAG, (a & b) == 0);
-	    		emu.regs.clear_flags(NEGATIVE_FLAG | CARRY_FLAG);
-	    		emu.regs.set_flags(HALFCARRY_FLAG);
-	    		8
-	    	}
+    		let (a,b) = (*emu.regs.a(), emu.read_hl());
+    		*emu.regs.a() &= b;
+    		emu.regs.update_flags(ZERO_FLAG, (a & b) == 0);
+    		emu.regs.clear_flags(NEGATIVE_FLAG | CARRY_FLAG);
+    		emu.regs.set_flags(HALFCARRY_FLAG);
+    		8
     	}
     };
 
@@ -357,13 +356,11 @@ macro_rules! or {
 
 	(hl) => {
     	|emu, _| {
-    		unsafe {
-    			let (a,b) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()));
-	    		*emu.regs.a() |= b;
-	    		emu.regs.update_flags(ZERO_FLAG, (a | b) == 0);
-	    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG | CARRY_FLAG);
-	    		8
-    		}
+			let (a,b) = (*emu.regs.a(), emu.read_hl());
+    		*emu.regs.a() |= b;
+    		emu.regs.update_flags(ZERO_FLAG, (a | b) == 0);
+    		emu.regs.clear_flags(NEGATIVE_FLAG | HALFCARRY_FLAG | CARRY_FLAG);
+    		8
     	}
     };
 
@@ -393,15 +390,13 @@ macro_rules! add {
 
 	(hl) => {
     	|emu, _| {
-    		unsafe {
-    			let (a,b) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()));
-	    		*emu.regs.a() = a.wrapping_add(b);
-	    		emu.regs.update_flags(ZERO_FLAG, a.wrapping_add(b) == 0);
-				emu.regs.clear_flags(NEGATIVE_FLAG);
-				emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) + (b & 0xF) > 0xF);
-				emu.regs.update_flags(CARRY_FLAG, a as u16 + b as u16 > 255);
-	    		8
-    		}
+			let (a,b) = (*emu.regs.a(), emu.read_hl());
+    		*emu.regs.a() = a.wrapping_add(b);
+    		emu.regs.update_flags(ZERO_FLAG, a.wrapping_add(b) == 0);
+			emu.regs.clear_flags(NEGATIVE_FLAG);
+			emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) + (b & 0xF) > 0xF);
+			emu.regs.update_flags(CARRY_FLAG, a as u16 + b as u16 > 255);
+    		8
     	}
     };
 
@@ -470,15 +465,13 @@ macro_rules! adc {
 
     (hl) => {
     	|emu, _| {
-    		unsafe {
-    			let (a,b,c) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()), emu.regs.get_flag(CARRY_FLAG) as u8);
-	    		*emu.regs.a() = a.wrapping_add(b).wrapping_add(c);
-	    		emu.regs.update_flags(ZERO_FLAG, a.wrapping_add(b).wrapping_add(c) == 0);
-				emu.regs.clear_flags(NEGATIVE_FLAG);
-				emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) + (b & 0xF) + c > 0xF);
-				emu.regs.update_flags(CARRY_FLAG, a as u16 + b as u16 + c as u16 > 255);
-	    		8
-    		}
+			let (a,b,c) = (*emu.regs.a(), emu.read_hl(), emu.regs.get_flag(CARRY_FLAG) as u8);
+    		*emu.regs.a() = a.wrapping_add(b).wrapping_add(c);
+    		emu.regs.update_flags(ZERO_FLAG, a.wrapping_add(b).wrapping_add(c) == 0);
+			emu.regs.clear_flags(NEGATIVE_FLAG);
+			emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) + (b & 0xF) + c > 0xF);
+			emu.regs.update_flags(CARRY_FLAG, a as u16 + b as u16 + c as u16 > 255);
+    		8
     	}
     };
 
@@ -510,15 +503,13 @@ macro_rules! sbc {
 
     (hl) => {
         |emu, _| {
-            unsafe {
-                let (a,b,c) = (*emu.regs.a(), emu.mem.rb(*emu.regs.hl()), emu.regs.get_flag(CARRY_FLAG) as u8);
-                *emu.regs.a() = a.wrapping_sub(b).wrapping_sub(c);
-                emu.regs.update_flags(ZERO_FLAG, a.wrapping_sub(b).wrapping_sub(c) == 0);
-                emu.regs.set_flags(NEGATIVE_FLAG);
-                emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) < (b & 0xF) + c);
-                emu.regs.update_flags(CARRY_FLAG, (a as u16) < b as u16 + c as u16);
-                8
-            }
+            let (a,b,c) = (*emu.regs.a(), emu.read_hl(), emu.regs.get_flag(CARRY_FLAG) as u8);
+            *emu.regs.a() = a.wrapping_sub(b).wrapping_sub(c);
+            emu.regs.update_flags(ZERO_FLAG, a.wrapping_sub(b).wrapping_sub(c) == 0);
+            emu.regs.set_flags(NEGATIVE_FLAG);
+            emu.regs.update_flags(HALFCARRY_FLAG, (a & 0xF) < (b & 0xF) + c);
+            emu.regs.update_flags(CARRY_FLAG, (a as u16) < b as u16 + c as u16);
+            8
         }
     };
 
@@ -577,7 +568,7 @@ pub const INSTRUCTIONS: [Instruction; 256] = [
 	new_instruction!("LD C,d8", 1, Some(&ld!(c, 8))),
 	new_instruction!("RRCA", 0, Some(&rrca)),
 	//0x10
-	new_instruction!("STOP 0", 0, Some(&stop)),
+	new_instruction!("STOP 0", 1, Some(&stop)),
 	new_instruction!("LD DE,d16", 2, Some(&ld!(de, 16))),
 	new_instruction!("LD (DE),A", 0, Some(&ld!(de, mem, a, 0))),
 	new_instruction!("INC DE", 0, Some(&inc!(de, 16))),
@@ -850,7 +841,8 @@ pub const INSTRUCTIONS: [Instruction; 256] = [
 
 //0x08
 fn ld_a16p_sp(emu: &mut Emulator, operand: u16) -> u64 {
-	emu.mem.ww(operand, emu.regs.sp);
+	let sp = emu.regs.sp;
+	emu.write_word(operand, sp);
 	20
 }
 
@@ -865,15 +857,15 @@ fn rrca(emu: &mut Emulator, _: u16) -> u64 {
 }
 
 //0x10
-// TODO: reimplement
 fn stop(emu: &mut Emulator, _: u16) -> u64 {
-    // Note: This command is used to switch a CGB into double speed mode
-    let speed_reg = emu.mem.rb(0xFF4D);
-    if emu.is_cgb() && speed_reg%2 == 1 {
-        emu.mem.wb(0xFF4D, speed_reg & 0x7E);
-        emu.mem.switch_speed();
-    }
-    4
+	// On a CGB with a speed switch armed (KEY1 bit 0), STOP switches speed instead of stopping
+	let key1 = emu.mem.rb(0xFF4D);
+	if emu.is_cgb() && key1 & 1 == 1 {
+		emu.switch_speed();
+	} else {
+		emu.stop();
+	}
+	4
 }
 
 //0x17
@@ -965,9 +957,7 @@ fn jr_nc(emu: &mut Emulator, operand: u16) -> u64 {
 
 //0x36
 fn ld_hlp_d8(emu: &mut Emulator, operand: u16) -> u64 {
-	unsafe {
-		emu.mem.wb(*emu.regs.hl(), operand as u8);
-	}
+	emu.write_hl(operand as u8);
 	12
 }
 
@@ -1004,13 +994,14 @@ fn rlca(emu: &mut Emulator, _: u16) -> u64 {
 
 //0x76
 fn halt(emu: &mut Emulator, _: u16) -> u64 {
-	emu.halted = true;
+	emu.halt();
 	4
 }
 
 //0xC0
 fn ret_nz(emu: &mut Emulator, operand: u16) -> u64 {
 	if !emu.regs.get_flag(ZERO_FLAG) {
+		emu.tick(4);
 		return ret(emu, operand)+4;
 	}
 	8
@@ -1041,6 +1032,7 @@ fn call_nz_a16(emu: &mut Emulator, operand: u16) -> u64 {
 //0xC8
 fn ret_z(emu: &mut Emulator, operand: u16) -> u64 {
 	if emu.regs.get_flag(ZERO_FLAG) {
+		emu.tick(4);
 		return ret(emu, operand)+4;
 	}
 	8
@@ -1048,8 +1040,7 @@ fn ret_z(emu: &mut Emulator, operand: u16) -> u64 {
 
 //0xC9
 fn ret(emu: &mut Emulator, _: u16) -> u64 {
-	emu.regs.pc = emu.mem.rw(emu.regs.sp);
-	emu.regs.sp += 2;
+	emu.regs.pc = emu.pop();
 	16
 }
 
@@ -1083,15 +1074,17 @@ fn call_z_a16(emu: &mut Emulator, operand: u16) -> u64 {
 
 //0xCD
 fn call_a16(emu: &mut Emulator, operand: u16) -> u64 {
-	emu.mem.ww(emu.regs.sp-2, emu.regs.pc);
+	let pc = emu.regs.pc;
+	emu.tick(4);
+	emu.push(pc);
 	emu.regs.pc = operand;
-	emu.regs.sp -= 2;
 	24
 }
 
 //0xD0
 fn ret_nc(emu: &mut Emulator, operand: u16) -> u64 {
 	if !emu.regs.get_flag(CARRY_FLAG) {
+		emu.tick(4);
 		return ret(emu, operand)+4;
 	}
 	8
@@ -1116,6 +1109,7 @@ fn call_nc_a16(emu: &mut Emulator, operand: u16) -> u64 {
 //0xD8
 fn ret_c(emu: &mut Emulator, operand: u16) -> u64 {
 	if emu.regs.get_flag(CARRY_FLAG) {
+		emu.tick(4);
 		return ret(emu, operand)+4;
 	}
 	8
@@ -1123,7 +1117,7 @@ fn ret_c(emu: &mut Emulator, operand: u16) -> u64 {
 
 //0xD9
 fn reti(emu: &mut Emulator, _: u16) -> u64 {
-	emu.enable_interrupts();
+	emu.interrupts.enable();
 	ret(emu, 0)
 }
 
@@ -1145,7 +1139,8 @@ fn call_c_a16(emu: &mut Emulator, operand: u16) -> u64 {
 
 //0xE0
 fn ldh_a8_a(emu: &mut Emulator, operand: u16) -> u64 {
-	emu.mem.wb(0xFF00 + operand, *emu.regs.a());
+	let a = *emu.regs.a();
+	emu.write(0xFF00 + operand, a);
 	12
 }
 
@@ -1159,19 +1154,20 @@ fn jp_hl(emu: &mut Emulator, _: u16) -> u64 {
 
 //0xEA
 fn ld_a16_a(emu: &mut Emulator, operand: u16) -> u64 {
-	emu.mem.wb(operand, *emu.regs.a());
+	let a = *emu.regs.a();
+	emu.write(operand, a);
 	16
 }
 
 //0xF0
 fn ldh_a_a8(emu: &mut Emulator, operand: u16) -> u64 {
-	*emu.regs.a() = emu.mem.rb(0xFF00 + operand);
+	*emu.regs.a() = emu.read(0xFF00 + operand);
 	12
 }
 
 //0xF4
 fn di(emu: &mut Emulator, _: u16) -> u64 {
-	emu.disable_interrupts();
+	emu.interrupts.disable();
 	4
 }
 
@@ -1199,13 +1195,13 @@ fn ld_sp_hl(emu: &mut Emulator, _: u16) -> u64 {
 
 //0xFA
 fn ld_a_a16(emu: &mut Emulator, operand: u16) -> u64 {
-	*emu.regs.a() = emu.mem.rb(operand);
+	*emu.regs.a() = emu.read(operand);
 	16
 }
 
 //0xFB
 fn ei(emu: &mut Emulator, _: u16) -> u64 {
-	emu.enable_interrupts();
+	emu.interrupts.enable_after_next_instruction();
 	4
 }
 
@@ -1213,7 +1209,6 @@ fn ei(emu: &mut Emulator, _: u16) -> u64 {
 mod test {
 	use super::*;
 	use emulator::emulator::Emulator;
-    use emulator::mbc::*;
     use emulator::cartridge::Cartridge;
 
 	#[test]
@@ -1265,15 +1260,15 @@ mod test {
 	#[test]
 	fn test_rst() {
 		let mut emu = Emulator::default();
-        emu.mem.cart = Mbc::NONE(Cartridge::new());
+        emu.mem.cart = Box::new(Cartridge::new());
         
-		emu.regs.sp = 3;
+		emu.regs.sp = 0xC003;
 		emu.regs.pc = 0xDEAD;
 		let rst_20 = INSTRUCTIONS[0x0E7].func.unwrap();
 		rst_20(&mut emu, 0);
-		assert_eq!(emu.regs.sp, 1);
-		assert_eq!(emu.mem.rb(2), 0xDE);
-		assert_eq!(emu.mem.rb(1), 0xAD);
+		assert_eq!(emu.regs.sp, 0xC001);
+		assert_eq!(emu.mem.rb(0xC002), 0xDE);
+		assert_eq!(emu.mem.rb(0xC001), 0xAD);
 		assert_eq!(emu.regs.pc, 0x20);
 	}
 	#[test]
@@ -1309,34 +1304,34 @@ mod test {
 	#[test]
 	fn test_call() {
 		let mut emu = Emulator::default();
-        emu.mem.cart = Mbc::NONE(Cartridge::new());
+        emu.mem.cart = Box::new(Cartridge::new());
 
-		emu.regs.sp = 100;
+		emu.regs.sp = 0xC064;
 		emu.regs.pc = 0xBEEF;
 		let call_a16 = INSTRUCTIONS[0xCD].func.unwrap();
 		call_a16(&mut emu, 50);
-		assert_eq!(emu.regs.sp, 98);
-		assert_eq!(emu.mem.rb(98), 0xEF);
-		assert_eq!(emu.mem.rb(99), 0xBE);
+		assert_eq!(emu.regs.sp, 0xC062);
+		assert_eq!(emu.mem.rb(0xC062), 0xEF);
+		assert_eq!(emu.mem.rb(0xC063), 0xBE);
 		assert_eq!(emu.regs.pc, 50);
 	}
 	#[test]
 	fn test_push_pop() {
 		let mut emu = Emulator::default();
-        emu.mem.cart = Mbc::NONE(Cartridge::new());
+        emu.mem.cart = Box::new(Cartridge::new());
 
-		emu.regs.sp = 100;
+		emu.regs.sp = 0xC064;
 		*emu.regs.a() = 2;
 		let push_af = INSTRUCTIONS[0xF5].func.unwrap();
 		push_af(&mut emu, 0);
 		unsafe {
-			assert_eq!(emu.regs.sp, 98);
+			assert_eq!(emu.regs.sp, 0xC062);
 			assert_eq!(*emu.regs.af(), 512);
 		}
 		let pop_hl = INSTRUCTIONS[0xE1].func.unwrap();
 		pop_hl(&mut emu, 0);
 		unsafe {
-			assert_eq!(emu.regs.sp, 100);
+			assert_eq!(emu.regs.sp, 0xC064);
 			assert_eq!(*emu.regs.hl(), 512);
 		}
 	}
@@ -1362,10 +1357,10 @@ mod test {
 	#[test]
 	fn test_ret() {
 		let mut emu = Emulator::default();
-        emu.mem.cart = Mbc::NONE(Cartridge::new());
+        emu.mem.cart = Box::new(Cartridge::new());
 
 		emu.regs.pc = 0x1000;
-		emu.regs.sp = 100;
+		emu.regs.sp = 0xC064;
 		let call = INSTRUCTIONS[0xCD].func.unwrap();
 		call(&mut emu, 0x20F0);
 		assert_eq!(emu.regs.pc, 0x20F0);
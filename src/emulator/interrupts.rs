@@ -1,36 +1,68 @@
+use std::io;
+use std::io::{Write, Read};
+
 use emulator::memory::Memory;
-use emulator::registers::Registers;
+use emulator::serialize::*;
 
 pub struct InterruptManager {
-	pub ime:	bool
+	pub ime:	bool,
+	// EI only turns on IME after the instruction following it, so this counts down the
+	// instructions left until then (0 if EI hasn't just run)
+	ei_delay:	u8
 }
 
 impl InterruptManager {
 	pub fn new() -> InterruptManager {
-		InterruptManager{ime: true}
+		InterruptManager{ime: true, ei_delay: 0}
+	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bool(out, self.ime)?;
+		write_u8(out, self.ei_delay)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.ime = read_bool(inp)?;
+		self.ei_delay = read_u8(inp)?;
+		Ok(())
 	}
 	pub fn request_interrupt(&self, mem: &mut Memory, id: u8) {
 		let interrupt_request_register = mem.rb(0xFF0F);
 		mem.wb(0xFF0F, interrupt_request_register | (1 << id));
 	}
-	//Returns true if an interrupt has occured
-	pub fn step(&mut self, mem: &mut Memory, regs: &mut Registers) -> bool {
-		if self.ime {
-			for i in 0..5 {
-				if (mem.rb(0xFFFF) & mem.rb(0xFF0F) & (1 << i)) > 0 {
-					//Interrupt both requested and enabled
-					self.ime = false;
-					let request = mem.rb(0xFF0F);
-					mem.wb(0xFF0F, request & !(1 << i));
-
-					mem.ww(regs.sp-2, regs.pc);
-					regs.sp -= 2;
-
-					regs.pc = 0x40 + 0x08*i;
-					return true;
-				}
-			}
+	// EI
+	pub fn enable_after_next_instruction(&mut self) {
+		if !self.ime && self.ei_delay == 0 {
+			self.ei_delay = 2;
 		}
-		false
 	}
-}
\ No newline at end of file
+	// RETI turns IME on straight away
+	pub fn enable(&mut self) {
+		self.ime = true;
+		self.ei_delay = 0;
+	}
+	// DI (which also cancels an EI that hasn't taken effect yet)
+	pub fn disable(&mut self) {
+		self.ime = false;
+		self.ei_delay = 0;
+	}
+	// Should be called after every instruction
+	pub fn instruction_finished(&mut self) {
+		if self.ei_delay > 0 {
+			self.ei_delay -= 1;
+			self.ime |= self.ei_delay == 0;
+		}
+	}
+	// Whether any interrupt is both requested and enabled (even if IME is off)
+	pub fn pending(&self, mem: &Memory) -> bool {
+		self.highest_pending(mem).is_some()
+	}
+	// The requested and enabled interrupt that gets serviced first (lower ids have priority)
+	pub fn highest_pending(&self, mem: &Memory) -> Option<u8> {
+		let pending = mem.rb(0xFFFF) & mem.rb(0xFF0F) & 0x1F;
+		if pending > 0 {Some(pending.trailing_zeros() as u8)} else {None}
+	}
+	// Clears the interrupt's request once it is being serviced
+	pub fn acknowledge(&self, mem: &mut Memory, id: u8) {
+		let request = mem.rb(0xFF0F);
+		mem.wb(0xFF0F, request & !(1 << id));
+	}
+}
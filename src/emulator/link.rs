@@ -0,0 +1,265 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use emulator::{Emulator, Event, SerialDevice, CYCLES_PER_FRAME};
+
+// Both ends of the cable stop and wait for each other this often
+// (one byte's worth of transfer on the internal clock)
+pub const CYCLES_PER_SYNC: i32 = 4096;
+
+// Every message is a kind followed by a single byte
+const SYNC: u8 = 0;
+const TRANSFER: u8 = 1;
+const REPLY: u8 = 2;
+
+// A link cable to another emulator over TCP
+//
+// The two sides run in lock-step: every CYCLES_PER_SYNC cycles each one tells the other it has
+// gotten that far and waits to hear the same back. A side driving the clock sends its byte right
+// away and waits for the reply, but the other side only looks at the socket when it reaches its
+// next sync point, so transfers land at the same point in emulated time no matter how fast the
+// connection is.
+pub struct TcpLink {
+	// None once the connection has been lost (after which it acts like nothing is plugged in)
+	stream:		Option<TcpStream>,
+	timer:		i32,
+	// How many sync points the other side has reached that this side hasn't yet
+	peer_syncs:	u32,
+	// The last byte this side had ready to go while waiting for the other side's clock
+	waiting:	Option<u8>,
+	received:	Option<u8>
+}
+
+impl TcpLink {
+	pub fn new(stream: TcpStream) -> io::Result<TcpLink> {
+		stream.set_nodelay(true)?;
+		Ok(TcpLink {
+			stream: Some(stream),
+			timer: CYCLES_PER_SYNC,
+			peer_syncs: 0,
+			waiting: None,
+			received: None
+		})
+	}
+	// Blocks until another emulator joins
+	pub fn host<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+		let listener = TcpListener::bind(address)?;
+		let (stream, _) = listener.accept()?;
+		TcpLink::new(stream)
+	}
+	pub fn join<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+		TcpLink::new(TcpStream::connect(address)?)
+	}
+	pub fn is_connected(&self) -> bool {
+		self.stream.is_some()
+	}
+	fn send(&mut self, kind: u8, val: u8) {
+		let sent = match self.stream {
+			Some(ref mut stream) => stream.write_all(&[kind, val]).is_ok(),
+			None => return
+		};
+		if !sent {
+			self.stream = None;
+		}
+	}
+	// Blocks until the next message arrives, handling it if it needs no further thought
+	// Returns the byte of any REPLY
+	fn receive(&mut self) -> Option<u8> {
+		let mut message = [0; 2];
+		let received = match self.stream {
+			Some(ref mut stream) => stream.read_exact(&mut message).is_ok(),
+			None => return None
+		};
+		if !received {
+			self.stream = None;
+			return None;
+		}
+
+		match message[0] {
+			SYNC => self.peer_syncs += 1,
+			TRANSFER => {
+				// Only take the other side's byte if there was one ready to go back
+				let reply = match self.waiting.take() {
+					Some(out) => {
+						self.received = Some(message[1]);
+						out
+					},
+					None => 0xFF
+				};
+				self.send(REPLY, reply);
+			},
+			REPLY => return Some(message[1]),
+			_ => self.stream = None
+		}
+		None
+	}
+	fn sync(&mut self) {
+		self.send(SYNC, 0);
+		while self.peer_syncs == 0 && self.is_connected() {
+			self.receive();
+		}
+		self.peer_syncs = self.peer_syncs.saturating_sub(1);
+	}
+}
+
+impl SerialDevice for TcpLink {
+	fn exchange(&mut self, out: u8) -> u8 {
+		// If both sides drive the clock at once, neither is listening for the other
+		self.waiting = None;
+		self.send(TRANSFER, out);
+		while self.is_connected() {
+			if let Some(val) = self.receive() {
+				return val;
+			}
+		}
+		0xFF
+	}
+	fn external_clock(&mut self, out: u8) -> Option<u8> {
+		let received = self.received.take();
+		self.waiting = if received.is_some() {None} else {Some(out)};
+		received
+	}
+	fn step(&mut self, cycles: i16) {
+		self.timer -= cycles as i32;
+		if self.timer <= 0 {
+			self.timer += CYCLES_PER_SYNC;
+			self.sync();
+		}
+		// Set again by external_clock if this side is still waiting after this step
+		self.waiting = None;
+	}
+}
+
+// The state of the cable between two emulators in the same process
+struct Wire {
+	// The byte each side has ready to go while waiting for the other side's clock
+	waiting:	[Option<u8>; 2],
+	// Bytes that have arrived at each side but that it hasn't taken yet
+	delivered:	[Option<u8>; 2]
+}
+
+// One end of a Wire
+struct LocalLink {
+	wire:	Rc<RefCell<Wire>>,
+	side:	usize
+}
+
+impl SerialDevice for LocalLink {
+	fn exchange(&mut self, out: u8) -> u8 {
+		let mut wire = self.wire.borrow_mut();
+		let other = 1 - self.side;
+		match wire.waiting[other].take() {
+			Some(val) => {
+				wire.delivered[other] = Some(out);
+				val
+			},
+			None => 0xFF
+		}
+	}
+	fn external_clock(&mut self, out: u8) -> Option<u8> {
+		let mut wire = self.wire.borrow_mut();
+		let received = wire.delivered[self.side].take();
+		wire.waiting[self.side] = if received.is_some() {None} else {Some(out)};
+		received
+	}
+	fn step(&mut self, _cycles: i16) {
+		// Set again by external_clock if this side is still waiting after this step
+		self.wire.borrow_mut().waiting[self.side] = None;
+	}
+}
+
+// Two emulators with their link ports plugged into each other
+// They are stepped one instruction at a time (whichever is behind goes next), so everything
+// that happens between them is completely reproducible
+pub struct LinkedPair {
+	pub emus:	[Emulator; 2],
+	cycles:		[u64; 2]
+}
+
+impl LinkedPair {
+	pub fn new(mut first: Emulator, mut second: Emulator) -> LinkedPair {
+		let wire = Rc::new(RefCell::new(Wire{waiting: [None; 2], delivered: [None; 2]}));
+		first.connect_serial(Box::new(LocalLink{wire: wire.clone(), side: 0}));
+		second.connect_serial(Box::new(LocalLink{wire: wire, side: 1}));
+		LinkedPair{emus: [first, second], cycles: [0; 2]}
+	}
+	// Steps whichever emulator is behind
+	// Returns which one it was, along with what Emulator::step returned
+	pub fn step(&mut self) -> (usize, u64, Option<Event>) {
+		// Both run at the same clock speed, so double speed counts half
+		let time = |i: usize| self.cycles[i]/self.emus[i].get_speed();
+		let i = if time(1) < time(0) {1} else {0};
+		let (cycles, event) = self.emus[i].step();
+		self.cycles[i] += cycles;
+		(i, cycles, event)
+	}
+	// Runs both emulators for (at least) a frame, ignoring any events
+	pub fn run_frame(&mut self) {
+		let target = [0, 1].iter().map(|&i| self.cycles[i]/self.emus[i].get_speed()).max().unwrap() + CYCLES_PER_FRAME;
+		while (0..2).any(|i| self.cycles[i]/self.emus[i].get_speed() < target) {
+			self.step();
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::thread;
+	use emulator::Serial;
+
+	// Runs a serial port connected to stream for a while, starting a transfer of out
+	// Returns the byte the port ended up with and whether a transfer finished
+	fn run_port(stream: TcpStream, out: u8, internal_clock: bool) -> (u8, bool) {
+		let mut serial = Serial::new();
+		serial.connect(Box::new(TcpLink::new(stream).unwrap()));
+		serial.wb(0xFF01, out);
+		serial.wb(0xFF02, if internal_clock {0x81} else {0x80});
+
+		let mut finished = false;
+		for _ in 0..(8*CYCLES_PER_SYNC/16) {
+			finished |= serial.step(16, false);
+		}
+		(serial.rb(0xFF01, false), finished)
+	}
+
+	#[test]
+	fn test_tcp_transfer() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		let joiner = thread::spawn(move || run_port(TcpStream::connect(address).unwrap(), 0x12, true));
+
+		let (stream, _) = listener.accept().unwrap();
+		assert_eq!(run_port(stream, 0x34, false), (0x12, true));
+		assert_eq!(joiner.join().unwrap(), (0x34, true));
+	}
+
+	// A game that sends val once (on the internal clock or not) and then spins forever
+	fn make_emulator(val: u8, sc: u8) -> Emulator {
+		let mut rom = vec![0; 0x8000];
+		// The side driving the clock waits a bit so the other side is ready to receive
+		let delay = if sc & 1 > 0 {0x10} else {0x01};
+		let code = [0x06, delay, 0x05, 0x20, 0xFD, 0x3E, val, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE];
+		rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+
+		let mut emu = Emulator::new();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.skip_bios();
+		emu
+	}
+
+	#[test]
+	fn test_linked_pair() {
+		for &(first_sc, second_sc) in &[(0x81, 0x80), (0x80, 0x81)] {
+			let mut pair = LinkedPair::new(make_emulator(0x12, first_sc), make_emulator(0x34, second_sc));
+			pair.run_frame();
+			assert_eq!(pair.emus[0].rb(0xFF01), 0x34);
+			assert_eq!(pair.emus[1].rb(0xFF01), 0x12);
+			// Both sides get the serial interrupt
+			assert!(pair.emus.iter().all(|emu| emu.rb(0xFF0F) & 8 > 0));
+		}
+	}
+}
@@ -1,4 +1,5 @@
-use std::fs::File;
+use std::io;
+use std::io::{Write, Read};
 
 use emulator::cartridge::Cartridge;
 use emulator::mbc1::Mbc1;
@@ -14,6 +15,20 @@ pub enum CartridgeType {
 	HUC1_RAM_BATTERY
 }
 
+// Reads until either buf is full or there is no more data, returning the number of bytes read
+pub fn read_fill<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+	let mut total = 0;
+	while total < buf.len() {
+		match reader.read(&mut buf[total..]) {
+			Ok(0) => break,
+			Ok(n) => total += n,
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+			Err(e) => return Err(e)
+		}
+	}
+	Ok(total)
+}
+
 impl CartridgeType {
 	pub fn from_code(code: u8) -> Option<CartridgeType> {
 		match code {
@@ -27,81 +42,62 @@ impl CartridgeType {
 	}
 }
 
-// Consider replacing with trait at some point
-pub enum Mbc {
-	EMPTY,
-	NONE(Cartridge),
-	MBC1(Mbc1),
-	MBC2(Mbc2),
-	MBC3(Mbc3),
-	MBC5(Mbc5)
-}
+// The hardware inside a cartridge that decides what the 0x0000-0x7FFF and 0xA000-0xBFFF
+// address ranges map to
+pub trait Mapper {
+	fn rb(&self, address: usize) -> u8;
+	fn wb(&mut self, address: usize, val: u8);
+	// Copies the game's ROM into the cartridge, returning the number of bytes used
+	fn load_game(&mut self, game: &[u8]) -> usize;
+	// Called every machine cycle (or more rarely, e.g. while halted) with the number of cycles that passed
+	fn step(&mut self, _cycles: i16) {}
 
-impl Mbc {
-	pub fn new(cartridge_type: CartridgeType) -> Mbc {
-		match cartridge_type {
-			CartridgeType::ROM_ONLY => Mbc::NONE(Cartridge::new()),
-			CartridgeType::MBC1 	=> Mbc::MBC1(Mbc1::new()),
-			CartridgeType::MBC2 	=> Mbc::MBC2(Mbc2::new()),
-			CartridgeType::MBC3 	=> Mbc::MBC3(Mbc3::new()),
-			CartridgeType::MBC5 	=> Mbc::MBC5(Mbc5::new()),
-			_						=> panic!("Unimplemented cartridge type: {:?}", cartridge_type)
-		}
+	// Battery-backed RAM (i.e. the game's save data)
+	fn load_sav(&mut self, _save: &mut Read) -> io::Result<usize> {
+		Ok(0)
 	}
-	pub fn rb(&self, address: usize) -> u8 {
-		match *self {
-			Mbc::EMPTY => panic!("Attempted to utilize empty cartridge"),
-			Mbc::NONE(ref cart) => cart.rb(address),
-			Mbc::MBC1(ref cart) => cart.rb(address),
-			Mbc::MBC2(ref cart) => cart.rb(address),
-			Mbc::MBC3(ref cart) => cart.rb(address),
-			Mbc::MBC5(ref cart) => cart.rb(address)
-		}
-	}
-	pub fn wb(&mut self, address: usize, val: u8) {
-		match *self {
-			Mbc::EMPTY => panic!("Attempted to utilize empty cartridge"),
-			Mbc::NONE(ref mut cart) => cart.wb(address, val),
-			Mbc::MBC1(ref mut cart) => cart.wb(address, val),
-			Mbc::MBC2(ref mut cart) => cart.wb(address, val),
-			Mbc::MBC3(ref mut cart) => cart.wb(address, val),
-			Mbc::MBC5(ref mut cart) => cart.wb(address, val)
-		}
+	fn save_game(&self, _save: &mut Write) -> io::Result<usize> {
+		Ok(0)
 	}
-	pub fn load_game(&mut self, game_file: &mut File) -> usize {
-		match *self {
-			Mbc::EMPTY => panic!("Attempted to utilize empty cartridge"),
-			Mbc::NONE(ref mut cart) => cart.load_game(game_file),
-			Mbc::MBC1(ref mut cart) => cart.load_game(game_file),
-			Mbc::MBC2(ref mut cart) => cart.load_game(game_file),
-			Mbc::MBC3(ref mut cart) => cart.load_game(game_file),
-			Mbc::MBC5(ref mut cart) => cart.load_game(game_file)
-		}
-	}
-	pub fn step(&mut self, cycles: i16) {
-		match *self {
-			Mbc::MBC3(ref mut cart) => cart.step(cycles),
-			_						=> {}
-		}
+
+	// Everything besides the ROM needed to restore the cartridge to its current state
+	fn save_state(&self, out: &mut Write) -> io::Result<()>;
+	fn load_state(&mut self, inp: &mut Read) -> io::Result<()>;
+
+	// Real time clock registers (seconds, minutes, hours, day low, day high), if the cartridge has one
+	fn rtc(&self) -> Option<[u8; 5]> {
+		None
 	}
-	pub fn load_sav(&mut self, save_file: &mut File) -> usize {
-		match *self {
-			Mbc::EMPTY => panic!("Attempted to utilize empty cartridge"),
-			Mbc::NONE(ref mut cart) => cart.load_sav(save_file),
-			Mbc::MBC1(ref mut cart) => cart.load_sav(save_file),
-			Mbc::MBC2(ref mut cart) => cart.load_sav(save_file),
-			Mbc::MBC3(ref mut cart) => cart.load_sav(save_file),
-			Mbc::MBC5(ref mut cart) => cart.load_sav(save_file)
-		}
+	fn set_rtc(&mut self, _rtc: [u8; 5]) {}
+}
+
+// Creates a mapper for a cartridge type registered with Emulator::register_mapper
+// Takes the ROM and RAM sizes (in bytes) from the cartridge header
+pub type MapperFactory = fn(usize, usize) -> Box<Mapper>;
+
+pub fn new_mapper(cartridge_type: CartridgeType, rom_size: usize, ram_size: usize) -> Option<Box<Mapper>> {
+	match cartridge_type {
+		CartridgeType::ROM_ONLY => Some(Box::new(Cartridge::new())),
+		CartridgeType::MBC1 	=> Some(Box::new(Mbc1::new(rom_size, ram_size))),
+		CartridgeType::MBC2 	=> Some(Box::new(Mbc2::new(rom_size))),
+		CartridgeType::MBC3 	=> Some(Box::new(Mbc3::new(rom_size, ram_size))),
+		CartridgeType::MBC5 	=> Some(Box::new(Mbc5::new(rom_size, ram_size))),
+		_						=> None
 	}
-	pub fn save_game(&mut self, save_file: &mut File) -> usize {
-		match *self {
-			Mbc::EMPTY => panic!("Attempted to utilize empty cartridge"),
-			Mbc::NONE(ref mut cart) => cart.save_game(save_file),
-			Mbc::MBC1(ref mut cart) => cart.save_game(save_file),
-			Mbc::MBC2(ref mut cart) => cart.save_game(save_file),
-			Mbc::MBC3(ref mut cart) => cart.save_game(save_file),
-			Mbc::MBC5(ref mut cart) => cart.save_game(save_file)
-		}
+}
+
+// Where in rom the given address (0x4000-0x7FFF) maps to with bank switched in
+// Bank numbers larger than the ROM wrap around like they do on real hardware
+pub fn rom_index(rom: &[u8], bank: usize, address: usize) -> usize {
+	(bank % (rom.len()/0x4000)) * 0x4000 + (address & 0x3FFF)
+}
+
+// Where in ram the given address (0xA000-0xBFFF) maps to with bank switched in
+// Returns None if the cartridge has no RAM
+pub fn ram_index(ram: &[u8], bank: usize, address: usize) -> Option<usize> {
+	if ram.is_empty() {
+		None
+	} else {
+		Some((bank*0x2000 + address%0x2000) % ram.len())
 	}
-}
\ No newline at end of file
+}
@@ -1,9 +1,12 @@
-use std::fs::File;
+use std::io;
 use std::io::{Write, Read};
 
+use emulator::mbc::{Mapper, read_fill, rom_index, ram_index};
+use emulator::serialize::*;
+
 pub struct Mbc1 {
-	rom:		Vec<u8>, //2MB ROM
-	ram:		Vec<u8>, //32KB RAM
+	rom:		Vec<u8>, //Up to 2MB ROM
+	ram:		Vec<u8>, //Up to 32KB RAM
 	rom_bank:	u8,				
 	ram_bank:	u8,
 	using_ram:	bool,
@@ -11,18 +14,21 @@ pub struct Mbc1 {
 }
 
 impl Mbc1 {
-	pub fn new() -> Mbc1 {
-		Mbc1{rom: vec![0; 0x200000], ram: vec![0; 0x008000], rom_bank: 1, ram_bank: 0, 
+	pub fn new(rom_size: usize, ram_size: usize) -> Mbc1 {
+		Mbc1{rom: vec![0; rom_size], ram: vec![0; ram_size], rom_bank: 1, ram_bank: 0, 
 				using_ram: false, mode: false}
 	}
-	pub fn rb(&self, address: usize) -> u8 {
+}
+
+impl Mapper for Mbc1 {
+	fn rb(&self, address: usize) -> u8 {
 		if address < 0x4000 {
 			self.rom[address]
 		} else if address < 0x8000 {
-			self.rom[(self.rom_bank as usize-1)*0x4000 + address]
+			self.rom[rom_index(&self.rom, self.rom_bank as usize, address)]
 		} else if 0xA000 <= address && address < 0xC000 {
 			if self.using_ram {
-				self.ram[self.ram_bank as usize*0x2000 + address%0x2000] 
+				ram_index(&self.ram, self.ram_bank as usize, address).map_or(0xFF, |index| self.ram[index])
 			} else {
 				0
 			}
@@ -30,7 +36,7 @@ impl Mbc1 {
 			panic!("Attempting to read from invalid MBC1 memory address: {:#X} ", address);
 		}
 	}
-	pub fn wb(&mut self, address: usize, val: u8) {
+	fn wb(&mut self, address: usize, val: u8) {
 		if address < 0x2000 {
 			self.using_ram = (val & 0xF) == 0xA;
 		} else if address < 0x4000 {
@@ -54,19 +60,37 @@ impl Mbc1 {
 			}
 		} else if 0xA000 <= address && address < 0xC000 {
 			if self.using_ram {
-				self.ram[self.ram_bank as usize*0x2000 + address%0x2000] = val;
+				if let Some(index) = ram_index(&self.ram, self.ram_bank as usize, address) {
+					self.ram[index] = val;
+				}
 			}
 		} else {
 			panic!("Attempting to write to invalid MBC1 memory address: {:#X}", address);
 		}
 	}
-	pub fn load_game(&mut self, game_file: &mut File) -> usize {
-		game_file.read(&mut self.rom).unwrap()
+	fn load_game(&mut self, game: &[u8]) -> usize {
+		read_fill(&mut &game[..], &mut self.rom).unwrap_or(0)
 	}
-	pub fn load_sav(&mut self, save_file: &mut File) -> usize {
-		save_file.read(&mut self.ram).unwrap()
+	fn load_sav(&mut self, save: &mut Read) -> io::Result<usize> {
+		read_fill(save, &mut self.ram)
 	}
-	pub fn save_game(&mut self, save_file: &mut File) -> usize {
-		save_file.write(&self.ram).unwrap()
+	fn save_game(&self, save: &mut Write) -> io::Result<usize> {
+		save.write_all(&self.ram).map(|_| self.ram.len())
 	}
-}
\ No newline at end of file
+	fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bytes(out, &self.ram)?;
+		write_u8(out, self.rom_bank)?;
+		write_u8(out, self.ram_bank)?;
+		write_bool(out, self.using_ram)?;
+		write_bool(out, self.mode)?;
+		Ok(())
+	}
+	fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		read_bytes(inp, &mut self.ram)?;
+		self.rom_bank = read_u8(inp)?;
+		self.ram_bank = read_u8(inp)?;
+		self.using_ram = read_bool(inp)?;
+		self.mode = read_bool(inp)?;
+		Ok(())
+	}
+}
@@ -1,22 +1,29 @@
-use std::fs::File;
+use std::io;
 use std::io::{Write, Read};
 
+use emulator::mbc::{Mapper, read_fill, rom_index};
+use emulator::serialize::*;
+
 pub struct Mbc2 {
-	rom:		Vec<u8>, //256KB ROM
+	rom:		Vec<u8>, //Up to 256KB ROM
 	ram:		Vec<u8>, //512x4bits (256 bytes) RAM
 	rom_bank:	u8,	
 	using_ram:	bool	
 }
 
 impl Mbc2 {
-	pub fn new() -> Mbc2 {
-		Mbc2{rom: vec![0; 0x40000], ram: vec![0; 0x00100], rom_bank: 1, using_ram: false}
+	// MBC2 has its RAM built in, so the header does not say how much there is
+	pub fn new(rom_size: usize) -> Mbc2 {
+		Mbc2{rom: vec![0; rom_size], ram: vec![0; 0x00100], rom_bank: 1, using_ram: false}
 	}
-	pub fn rb(&self, address: usize) -> u8 {
+}
+
+impl Mapper for Mbc2 {
+	fn rb(&self, address: usize) -> u8 {
 		if address < 0x4000 {
 			self.rom[address]
 		} else if address < 0x8000 {
-			self.rom[(self.rom_bank as usize-1)*0x4000 + address]
+			self.rom[rom_index(&self.rom, self.rom_bank as usize, address)]
 		} else if 0xA000 <= address && address < 0xA200 {
 			if self.using_ram {
 				if address >= 0xA100 {
@@ -31,14 +38,14 @@ impl Mbc2 {
 			panic!("Attempting to read from invalid MBC2 memory address: {:#X} ", address);
 		}
 	}
-	pub fn wb(&mut self, address: usize, val: u8) {
+	fn wb(&mut self, address: usize, val: u8) {
 		if address < 0x2000 {
 			if address & 0x100 == 0 {
 				self.using_ram = (val & 0xF) == 0xA;
 			}
 		} else if address < 0x4000 {
 			if address & 0x100 > 0 {
-				self.rom_bank = val & 0xF;
+				self.rom_bank = if val & 0xF > 0 {val & 0xF} else {1};
 			}
 		} else if 0xA000 <= address && address < 0xA200 {
 			let ram_address = address%0x0100;
@@ -53,13 +60,25 @@ impl Mbc2 {
 			panic!("Attempting to write to invalid MBC2 memory address: {:#X}", address);
 		}
 	}
-	pub fn load_game(&mut self, game_file: &mut File) -> usize {
-		game_file.read(&mut self.rom).unwrap()
+	fn load_game(&mut self, game: &[u8]) -> usize {
+		read_fill(&mut &game[..], &mut self.rom).unwrap_or(0)
 	}
-	pub fn load_sav(&mut self, save_file: &mut File) -> usize {
-		save_file.read(&mut self.ram).unwrap()
+	fn load_sav(&mut self, save: &mut Read) -> io::Result<usize> {
+		read_fill(save, &mut self.ram)
 	}
-	pub fn save_game(&mut self, save_file: &mut File) -> usize {
-		save_file.write(&self.ram).unwrap()
+	fn save_game(&self, save: &mut Write) -> io::Result<usize> {
+		save.write_all(&self.ram).map(|_| self.ram.len())
 	}
-}
\ No newline at end of file
+	fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bytes(out, &self.ram)?;
+		write_u8(out, self.rom_bank)?;
+		write_bool(out, self.using_ram)?;
+		Ok(())
+	}
+	fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		read_bytes(inp, &mut self.ram)?;
+		self.rom_bank = read_u8(inp)?;
+		self.using_ram = read_bool(inp)?;
+		Ok(())
+	}
+}
@@ -1,11 +1,14 @@
-use std::fs::File;
+use std::io;
 use std::io::{Write, Read};
 
+use emulator::mbc::{Mapper, read_fill, rom_index, ram_index};
+use emulator::serialize::*;
+
 const RTC_RESETS: [u8; 3] = [60, 60, 24];
 
 pub struct Mbc3 {
-	rom:		Vec<u8>, //2MB ROM
-	ram:		Vec<u8>, //32KB RAM
+	rom:		Vec<u8>, //Up to 2MB ROM
+	ram:		Vec<u8>, //Up to 32KB RAM
 	rtc:		[u8; 5], //5 clock registers
 	latch:		[u8; 5], //"lateched" clock values
 	rom_bank:	u8,				
@@ -20,10 +23,10 @@ pub struct Mbc3 {
 }
 
 impl Mbc3 {
-	pub fn new() -> Mbc3 {
+	pub fn new(rom_size: usize, ram_size: usize) -> Mbc3 {
 		Mbc3 {
-			rom: vec![0; 0x200000], 
-			ram: vec![0; 0x008000], 
+			rom: vec![0; rom_size], 
+			ram: vec![0; ram_size], 
 			rtc: [0; 5],
 			latch: [0; 5],
 			rom_bank: 1, 
@@ -37,11 +40,33 @@ impl Mbc3 {
 			using_clk: true
 		}
 	}
-	pub fn rb(&self, address: usize) -> u8 {
+	fn increment_rtc(&mut self, index: usize) {
+		if index == 3 {
+			let mut day_counter = self.rtc[3] as u16 | 0x100*(self.rtc[4] & 1) as u16;
+			if day_counter == 0x1FF {
+				day_counter = 0;
+				self.rtc[4] |= 1 << 7;
+			} else {
+				day_counter += 1;
+			}
+			self.rtc[3] = (day_counter & 0xFF) as u8;
+			self.rtc[4] = (self.rtc[4] & 0xFE) | ((day_counter >> 8) as u8);
+		} else {
+			self.rtc[index] += 1;
+			if self.rtc[index] == RTC_RESETS[index] {
+				self.rtc[index] = 0;
+				self.increment_rtc(index+1);
+			}
+		}
+	}
+}
+
+impl Mapper for Mbc3 {
+	fn rb(&self, address: usize) -> u8 {
 		if address < 0x4000 {
 			self.rom[address]
 		} else if address < 0x8000 {
-			self.rom[(self.rom_bank as usize-1)*0x4000 + address]
+			self.rom[rom_index(&self.rom, self.rom_bank as usize, address)]
 		} else if 0xA000 <= address && address < 0xC000 {
 			if self.using_ram {
 				if self.mode {
@@ -51,7 +76,7 @@ impl Mbc3 {
 						self.latch[self.rtc_reg as usize]
 					}
 				} else {
-					self.ram[self.ram_bank as usize*0x2000 + address%0x2000]
+					ram_index(&self.ram, self.ram_bank as usize, address).map_or(0xFF, |index| self.ram[index])
 				} 
 			} else {
 				0
@@ -60,7 +85,7 @@ impl Mbc3 {
 			panic!("Attempting to read from invalid MBC3 memory address: {:#X}", address);
 		}
 	}
-	pub fn wb(&mut self, address: usize, val: u8) {
+	fn wb(&mut self, address: usize, val: u8) {
 		if address < 0x2000 {
 			self.using_ram = (val & 0xF) == 0xA;
 		} else if address < 0x4000 {
@@ -87,34 +112,15 @@ impl Mbc3 {
 					if self.using_clk {
 						self.rtc[self.rtc_reg as usize] = val;
 					}
-				} else {
-					self.ram[self.ram_bank as usize*0x2000 + address%0x2000] = val;
+				} else if let Some(index) = ram_index(&self.ram, self.ram_bank as usize, address) {
+					self.ram[index] = val;
 				}
 			}
 		} else {
 			panic!("Attempting to write to invalid MBC3 memory address: {:#X}", address);
 		}
 	}
-	fn increment_rtc(&mut self, index: usize) {
-		if index == 3 {
-			let mut day_counter = self.rtc[3] as u16 | 0x100*(self.rtc[4] & 1) as u16;
-			if day_counter == 0x1FF {
-				day_counter = 0;
-				self.rtc[4] |= 1 << 7;
-			} else {
-				day_counter += 1;
-			}
-			self.rtc[3] = (day_counter & 0xFF) as u8;
-			self.rtc[4] = (self.rtc[4] & 0xFE) | ((day_counter >> 8) as u8);
-		} else {
-			self.rtc[index] += 1;
-			if self.rtc[index] == RTC_RESETS[index] {
-				self.rtc[index] = 0;
-				self.increment_rtc(index+1);
-			}
-		}
-	}
-	pub fn step(&mut self, cycles: i16) {
+	fn step(&mut self, cycles: i16) {
 		if self.rtc[4] & (1 << 6) == 0 {
 			self.counter -= cycles;
 		}
@@ -130,13 +136,49 @@ impl Mbc3 {
 			}
 		}
 	}
-	pub fn load_game(&mut self, game_file: &mut File) -> usize {
-		game_file.read(&mut self.rom).unwrap()
+	fn load_game(&mut self, game: &[u8]) -> usize {
+		read_fill(&mut &game[..], &mut self.rom).unwrap_or(0)
 	}
-	pub fn load_sav(&mut self, save_file: &mut File) -> usize {
-		save_file.read(&mut self.ram).unwrap()
+	fn load_sav(&mut self, save: &mut Read) -> io::Result<usize> {
+		read_fill(save, &mut self.ram)
 	}
-	pub fn save_game(&mut self, save_file: &mut File) -> usize {
-		save_file.write(&self.ram).unwrap()
+	fn save_game(&self, save: &mut Write) -> io::Result<usize> {
+		save.write_all(&self.ram).map(|_| self.ram.len())
 	}
-}
\ No newline at end of file
+	fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bytes(out, &self.ram)?;
+		write_bytes(out, &self.rtc)?;
+		write_bytes(out, &self.latch)?;
+		write_u8(out, self.rom_bank)?;
+		write_u8(out, self.ram_bank)?;
+		write_u8(out, self.rtc_reg)?;
+		write_bool(out, self.using_ram)?;
+		write_bool(out, self.mode)?;
+		write_u16(out, self.clock)?;
+		write_i16(out, self.counter)?;
+		write_u8(out, self.prev_val)?;
+		write_bool(out, self.using_clk)?;
+		Ok(())
+	}
+	fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		read_bytes(inp, &mut self.ram)?;
+		read_bytes(inp, &mut self.rtc)?;
+		read_bytes(inp, &mut self.latch)?;
+		self.rom_bank = read_u8(inp)?;
+		self.ram_bank = read_u8(inp)?;
+		self.rtc_reg = read_u8(inp)?;
+		self.using_ram = read_bool(inp)?;
+		self.mode = read_bool(inp)?;
+		self.clock = read_u16(inp)?;
+		self.counter = read_i16(inp)?;
+		self.prev_val = read_u8(inp)?;
+		self.using_clk = read_bool(inp)?;
+		Ok(())
+	}
+	fn rtc(&self) -> Option<[u8; 5]> {
+		Some(self.rtc)
+	}
+	fn set_rtc(&mut self, rtc: [u8; 5]) {
+		self.rtc = rtc;
+	}
+}
@@ -1,28 +1,34 @@
-use std::fs::File;
+use std::io;
 use std::io::{Write, Read};
 
+use emulator::mbc::{Mapper, read_fill, rom_index, ram_index};
+use emulator::serialize::*;
+
 pub struct Mbc5 {
 	//Unknown error occurs when using an array instead of a Vec
-	rom:		Vec<u8>, //8MB ROM
-	ram:		Vec<u8>, //128KB RAM
+	rom:		Vec<u8>, //Up to 8MB ROM
+	ram:		Vec<u8>, //Up to 128KB RAM
 	rom_bank:	u16,				
 	ram_bank:	u8,
 	using_ram:	bool	
 }
 
 impl Mbc5 {
-	pub fn new() -> Mbc5 {
-		Mbc5{rom: vec![0; 0x800000], ram: vec![0; 0x020000], rom_bank: 0, ram_bank: 0, 
+	pub fn new(rom_size: usize, ram_size: usize) -> Mbc5 {
+		Mbc5{rom: vec![0; rom_size], ram: vec![0; ram_size], rom_bank: 0, ram_bank: 0, 
 			using_ram: false}
 	}
-	pub fn rb(&self, address: usize) -> u8 {
+}
+
+impl Mapper for Mbc5 {
+	fn rb(&self, address: usize) -> u8 {
 		if address < 0x4000 {
 			self.rom[address]
 		} else if address < 0x8000 {
-			self.rom[self.rom_bank as usize * 0x4000 | (address & 0x3FFF)]
+			self.rom[rom_index(&self.rom, self.rom_bank as usize, address)]
 		} else if 0xA000 <= address && address < 0xC000 {
 			if self.using_ram {
-				self.ram[self.ram_bank as usize*0x2000 + address%0x2000] 
+				ram_index(&self.ram, self.ram_bank as usize, address).map_or(0xFF, |index| self.ram[index])
 			} else {
 				0
 			}
@@ -30,7 +36,7 @@ impl Mbc5 {
 			panic!("Attempting to read from invalid MBC5 memory address: {:#X} ", address);
 		}
 	}
-	pub fn wb(&mut self, address: usize, val: u8) {
+	fn wb(&mut self, address: usize, val: u8) {
 		if address < 0x2000 {
 			self.using_ram = (val & 0xF) == 0xA;
 		} else if address < 0x3000 {
@@ -41,19 +47,35 @@ impl Mbc5 {
 			self.ram_bank = val & 0xF;
 		} else if 0xA000 <= address && address < 0xC000 {
 			if self.using_ram {
-				self.ram[self.ram_bank as usize*0x2000 + address%0x2000] = val;
+				if let Some(index) = ram_index(&self.ram, self.ram_bank as usize, address) {
+					self.ram[index] = val;
+				}
 			}
 		} else {
 			//panic!("Attempting to write to invalid MBC5 memory address: {:#X}", address);
 		}
 	}
-	pub fn load_game(&mut self, game_file: &mut File) -> usize {
-		game_file.read(&mut self.rom).unwrap()
+	fn load_game(&mut self, game: &[u8]) -> usize {
+		read_fill(&mut &game[..], &mut self.rom).unwrap_or(0)
 	}
-	pub fn load_sav(&mut self, save_file: &mut File) -> usize {
-		save_file.read(&mut self.ram).unwrap()
+	fn load_sav(&mut self, save: &mut Read) -> io::Result<usize> {
+		read_fill(save, &mut self.ram)
 	}
-	pub fn save_game(&mut self, save_file: &mut File) -> usize {
-		save_file.write(&self.ram).unwrap()
+	fn save_game(&self, save: &mut Write) -> io::Result<usize> {
+		save.write_all(&self.ram).map(|_| self.ram.len())
 	}
-}
\ No newline at end of file
+	fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bytes(out, &self.ram)?;
+		write_u16(out, self.rom_bank)?;
+		write_u8(out, self.ram_bank)?;
+		write_bool(out, self.using_ram)?;
+		Ok(())
+	}
+	fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		read_bytes(inp, &mut self.ram)?;
+		self.rom_bank = read_u16(inp)?;
+		self.ram_bank = read_u8(inp)?;
+		self.using_ram = read_bool(inp)?;
+		Ok(())
+	}
+}
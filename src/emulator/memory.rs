@@ -1,7 +1,16 @@
-use emulator::Mbc;
+use std::io;
+use std::io::{Write, Read};
+
+use emulator::Mapper;
+use emulator::serialize::*;
+use emulator::Apu;
+use emulator::Serial;
+use emulator::cartridge::Cartridge;
 
 pub struct Memory {
-	pub cart:		Mbc,
+	pub cart:		Box<Mapper>,
+	pub apu:		Apu,
+	pub serial:		Serial,
 	pub bios:		Vec<u8>, 	//Size depends on GB/GBC
 	pub save_file: 	String,
 	pub cgb_mode: 	bool,
@@ -25,7 +34,9 @@ impl Memory {
 			bgp: [0; 64], 
 			sp: [0; 64],
 			bios: Vec::new(), 
-			cart: Mbc::EMPTY, 
+			cart: Box::new(Cartridge::new()), 
+			apu: Apu::new(),
+			serial: Serial::new(),
 			save_file: String::new(),
 			wram_bank: 1, 
 			key_state: 0xFF, 
@@ -33,6 +44,38 @@ impl Memory {
 			cgb_mode: false
 		}
 	}
+	// Saves everything but the BIOS and the cartridge's ROM (which should already be loaded)
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bytes(out, &self.mem)?;
+		write_bytes(out, &self.wram)?;
+		write_bytes(out, &self.vram)?;
+		write_bytes(out, &self.bgp)?;
+		write_bytes(out, &self.sp)?;
+		write_u8(out, self.wram_bank)?;
+		write_u8(out, self.key_state)?;
+		write_bool(out, self.running_bios)?;
+		write_bool(out, self.cgb_mode)?;
+		self.cart.save_state(out)?;
+		self.apu.save_state(out)?;
+		self.serial.save_state(out)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		read_bytes(inp, &mut self.mem)?;
+		read_bytes(inp, &mut self.wram)?;
+		read_bytes(inp, &mut self.vram)?;
+		read_bytes(inp, &mut self.bgp)?;
+		read_bytes(inp, &mut self.sp)?;
+		self.wram_bank = read_u8(inp)? & 7;
+		self.key_state = read_u8(inp)?;
+		self.running_bios = read_bool(inp)?;
+		self.cgb_mode = read_bool(inp)?;
+		self.cart.load_state(inp)?;
+		self.apu.load_state(inp)?;
+		self.serial.load_state(inp)
+	}
+	pub fn is_running_bios(&self) -> bool {
+		self.running_bios
+	}
 	pub fn finished_with_bios(&mut self) {
 		self.running_bios = false;
 	}
@@ -67,6 +110,10 @@ impl Memory {
 				0x20 => 0x20 | (self.key_state & 0xF),
 				_ => 0
 			}
+		} else if 0xFF01 == address || 0xFF02 == address { //Serial transfer data and control
+			self.serial.rb(address as u16, self.cgb_mode)
+		} else if 0xFF10 <= address && address < 0xFF40 { //Sound registers and wave RAM
+			self.apu.rb(address as u16)
 		} else if 0xFF55 == address {
 			if self.mem[0xFF55] == 0xFF {0xFF} else {self.mem[0xFF55] & 0x7F}
 		} else if 0xFF69 == address { //Background Palette Data
@@ -106,6 +153,10 @@ impl Memory {
 			self.mem[address - 0x2000] = val;
 		} else if 0xFF04 == address { //divider register (DIV)
 			return self.mem[0xFF04] = 0;
+		} else if 0xFF01 == address || 0xFF02 == address { //Serial transfer data and control
+			return self.serial.wb(address as u16, val);
+		} else if 0xFF10 <= address && address < 0xFF40 { //Sound registers and wave RAM
+			return self.apu.wb(address as u16, val);
 		} else if 0xFF44 == address { //scanline position
 			return self.mem[0xFF44] = 0;
 		} else if 0xFF46 == address { //OAM DMA transfer
@@ -162,6 +213,9 @@ impl Memory {
 	pub fn wl(&mut self, val: u8) {
 		self.mem[0xFF44] = val;
 	}
+	pub fn key_state(&self) -> u8 {
+		self.key_state
+	}
 	//write keys
 	pub fn wk(&mut self, key: u8, pressed: bool) {
 		if pressed {
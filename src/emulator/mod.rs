@@ -1,23 +1,39 @@
 pub mod emulator;
 pub mod gpu;
 pub mod memory;
+pub mod registers;
+pub mod serialize;
 
-mod registers;
 mod instructions;
 mod rom_info;
 mod cb_instructions;
 mod interrupts;
 mod timers;
+mod apu;
+mod channels;
+mod serial;
+pub mod wav;
+pub mod link;
+pub mod printer;
+pub mod rewind;
+pub mod movie;
+pub mod screenshot;
+pub mod testrom;
 mod mbc;
 mod cartridge;
+mod error;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
 
-pub use self::emulator::Emulator;
+pub use self::emulator::{Emulator, Event, CYCLES_PER_FRAME, STATE_VERSION};
 pub use self::gpu::{Gpu, Color};
 pub use self::interrupts::InterruptManager;
 pub use self::memory::Memory;
 pub use self::timers::Timers;
-pub use self::mbc::Mbc;
\ No newline at end of file
+pub use self::serial::{Serial, SerialDevice, SerialConsole, Disconnected};
+pub use self::apu::{Apu, SAMPLE_RATE, SCOPE_LENGTH};
+pub use self::mbc::{Mapper, MapperFactory};
+pub use self::error::{LoadError, MovieError};
+pub use self::registers::Registers;
\ No newline at end of file
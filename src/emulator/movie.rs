@@ -0,0 +1,285 @@
+use std::io;
+use std::io::{Write, Read};
+
+use emulator::{Emulator, MovieError};
+use emulator::serialize::*;
+
+const MOVIE_MAGIC: &'static [u8; 4] = b"RGBM";
+// Bump this whenever the movie format changes
+pub const MOVIE_VERSION: u32 = 1;
+// The emulator's state is checked against the movie this often during playback
+const FRAMES_PER_HASH: usize = 60;
+
+// One frame of input: the buttons held (see Emulator::get_joypad) and exactly how many cycles ran
+// Storing the cycles means frames don't all have to be the same length (e.g. when paused mid-frame)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovieFrame {
+	pub buttons:	u8,
+	pub cycles:		u32
+}
+
+// A recording of everything needed to replay a session exactly
+pub struct Movie {
+	pub rom_hash:		u64,
+	// The save state the movie starts from, or None if it starts from power-on
+	pub start_state:	Option<Vec<u8>>,
+	pub rtc:			Option<[u8; 5]>,
+	// Hash of the emulator's state right before the first frame
+	pub start_hash:		u64,
+	pub frames:			Vec<MovieFrame>,
+	// Hash of the emulator's state after every FRAMES_PER_HASH frames
+	pub hashes:			Vec<u64>
+}
+
+fn state_hash(emu: &Emulator) -> u64 {
+	hash_bytes(&emu.export_state())
+}
+
+impl Movie {
+	pub fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		out.write_all(MOVIE_MAGIC)?;
+		write_u32(out, MOVIE_VERSION)?;
+		write_u64(out, self.rom_hash)?;
+		write_bool(out, self.start_state.is_some())?;
+		if let Some(ref state) = self.start_state {
+			write_bytes(out, state)?;
+		}
+		write_bool(out, self.rtc.is_some())?;
+		if let Some(ref rtc) = self.rtc {
+			write_bytes(out, rtc)?;
+		}
+		write_u64(out, self.start_hash)?;
+
+		write_u32(out, self.frames.len() as u32)?;
+		for frame in &self.frames {
+			write_u8(out, frame.buttons)?;
+			write_u32(out, frame.cycles)?;
+		}
+		write_u32(out, self.hashes.len() as u32)?;
+		for &hash in &self.hashes {
+			write_u64(out, hash)?;
+		}
+		Ok(())
+	}
+	pub fn load<R: Read>(inp: &mut R) -> Result<Movie, MovieError> {
+		let mut magic = [0; 4];
+		inp.read_exact(&mut magic)?;
+		if &magic != MOVIE_MAGIC {
+			return Err(MovieError::BadMovie("not a movie".to_string()));
+		}
+		let version = read_u32(inp)?;
+		if version != MOVIE_VERSION {
+			return Err(MovieError::BadMovie(format!("version {} is not supported (expected {})", version, MOVIE_VERSION)));
+		}
+
+		let rom_hash = read_u64(inp)?;
+		let start_state = if read_bool(inp)? {
+			// The length can't be trusted until that much has actually been read
+			let len = read_u32(inp)? as u64;
+			let mut state = Vec::new();
+			inp.take(len).read_to_end(&mut state)?;
+			if (state.len() as u64) < len {
+				return Err(MovieError::BadMovie(format!("start state is truncated ({} of {} bytes)", state.len(), len)));
+			}
+			Some(state)
+		} else {
+			None
+		};
+		let rtc = if read_bool(inp)? {
+			let mut rtc = [0; 5];
+			read_bytes(inp, &mut rtc)?;
+			Some(rtc)
+		} else {
+			None
+		};
+		let start_hash = read_u64(inp)?;
+
+		let mut frames = Vec::new();
+		for _ in 0..read_u32(inp)? {
+			frames.push(MovieFrame{buttons: read_u8(inp)?, cycles: read_u32(inp)?});
+		}
+		let mut hashes = Vec::new();
+		for _ in 0..read_u32(inp)? {
+			hashes.push(read_u64(inp)?);
+		}
+		Ok(Movie {
+			rom_hash: rom_hash,
+			start_state: start_state,
+			rtc: rtc,
+			start_hash: start_hash,
+			frames: frames,
+			hashes: hashes
+		})
+	}
+}
+
+pub struct MovieRecorder {
+	movie:	Movie
+}
+
+impl MovieRecorder {
+	// Starts recording from the emulator's current state
+	// If from_power_on is true, the emulator should have just loaded the game and the movie
+	// will only play back on an emulator that has also just loaded it (in the same way)
+	pub fn new(emu: &Emulator, from_power_on: bool) -> MovieRecorder {
+		MovieRecorder {
+			movie: Movie {
+				rom_hash: emu.rom_hash(),
+				start_state: if from_power_on {None} else {Some(emu.export_state())},
+				rtc: emu.get_rtc(),
+				start_hash: state_hash(emu),
+				frames: Vec::new(),
+				hashes: Vec::new()
+			}
+		}
+	}
+	// Should be called after every frame with the number of cycles that ran during it
+	// The buttons can only change between frames
+	pub fn record_frame(&mut self, emu: &Emulator, cycles: u64) {
+		self.movie.frames.push(MovieFrame{buttons: emu.get_joypad(), cycles: cycles as u32});
+		if self.movie.frames.len()%FRAMES_PER_HASH == 0 {
+			self.movie.hashes.push(state_hash(emu));
+		}
+	}
+	pub fn num_frames(&self) -> usize {
+		self.movie.frames.len()
+	}
+	pub fn finish(self) -> Movie {
+		self.movie
+	}
+}
+
+pub struct MoviePlayer {
+	movie:	Movie,
+	frame:	usize
+}
+
+impl MoviePlayer {
+	// Gets the emulator ready to play back the movie
+	// For movies recorded from power-on, the emulator should have just loaded the game
+	pub fn new(movie: Movie, emu: &mut Emulator) -> Result<MoviePlayer, MovieError> {
+		if movie.rom_hash != emu.rom_hash() {
+			return Err(MovieError::WrongRom);
+		}
+		if let Some(ref state) = movie.start_state {
+			emu.import_state(state)?;
+		}
+		if let Some(rtc) = movie.rtc {
+			emu.set_rtc(rtc);
+		}
+		if state_hash(emu) != movie.start_hash {
+			return Err(MovieError::Desync(0));
+		}
+		Ok(MoviePlayer{movie: movie, frame: 0})
+	}
+	// Runs the emulator for the next frame of the movie
+	// Returns the number of cycles that passed, or None once the movie is over
+	pub fn play_frame(&mut self, emu: &mut Emulator) -> Result<Option<u64>, MovieError> {
+		let frame = match self.movie.frames.get(self.frame) {
+			Some(&frame) => frame,
+			None => return Ok(None)
+		};
+		emu.set_joypad(frame.buttons);
+		let mut cycles = 0;
+		while cycles < frame.cycles as u64 {
+			cycles += emu.step().0;
+		}
+		self.frame += 1;
+
+		if self.frame%FRAMES_PER_HASH == 0 {
+			match self.movie.hashes.get(self.frame/FRAMES_PER_HASH - 1) {
+				Some(&hash) if hash != state_hash(emu) => return Err(MovieError::Desync(self.frame)),
+				_ => {}
+			}
+		}
+		Ok(Some(cycles))
+	}
+	// How many frames have been played so far
+	pub fn frame(&self) -> usize {
+		self.frame
+	}
+	pub fn is_finished(&self) -> bool {
+		self.frame >= self.movie.frames.len()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	// A game that adds up the joypad's direction keys in RAM forever
+	fn make_emulator() -> Emulator {
+		let mut rom = vec![0; 0x8000];
+		let code = [0x3E, 0x20, 0xE0, 0x00, 0xF0, 0x00, 0x21, 0x00, 0xC0, 0x86, 0x77, 0x18, 0xF7];
+		rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+		let mut emu = Emulator::new();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.skip_bios();
+		emu
+	}
+	fn record(emu: &mut Emulator, from_power_on: bool) -> Movie {
+		let mut recorder = MovieRecorder::new(emu, from_power_on);
+		for i in 0..150 {
+			emu.set_joypad(if i%7 < 3 {0x10} else {0x01});
+			let cycles = emu.run_frame();
+			recorder.record_frame(emu, cycles);
+		}
+		recorder.finish()
+	}
+
+	#[test]
+	fn test_playback() {
+		let mut emu = make_emulator();
+		emu.run_frame();
+		let movie = record(&mut emu, false);
+		let end = emu.export_state();
+
+		let mut saved = Vec::new();
+		movie.save(&mut saved).unwrap();
+		let movie = Movie::load(&mut &saved[..]).unwrap();
+		assert_eq!(movie.frames.len(), 150);
+
+		let mut emu = make_emulator();
+		let mut player = MoviePlayer::new(movie, &mut emu).unwrap();
+		while let Some(_) = player.play_frame(&mut emu).unwrap() {}
+		assert!(player.is_finished());
+		assert_eq!(emu.export_state(), end);
+
+		// A movie cut off in its start state is rejected, even if the state's length is nonsense
+		let mut truncated = saved[..100].to_vec();
+		truncated[17..21].copy_from_slice(&[0xFF; 4]);
+		match Movie::load(&mut &truncated[..]) {
+			Err(MovieError::BadMovie(_)) => {},
+			other => panic!("Unexpected result: {:?}", other.map(|_| ()).map_err(|e| e.to_string()))
+		}
+	}
+	#[test]
+	fn test_desync() {
+		let mut emu = make_emulator();
+		let mut movie = record(&mut emu, true);
+		let mut saved = Vec::new();
+		movie.save(&mut saved).unwrap();
+
+		// An emulator in a different state can't start the movie
+		let mut other = make_emulator();
+		other.run_frame();
+		match MoviePlayer::new(Movie::load(&mut &saved[..]).unwrap(), &mut other) {
+			Err(MovieError::Desync(0)) => {},
+			Err(e) => panic!("Unexpected error: {}", e),
+			Ok(_) => panic!("Movie should not have started")
+		}
+
+		// Pressing something else changes where the game ends up, which is caught at the first check
+		movie.frames[10].buttons = 0x04;
+		let mut emu = make_emulator();
+		let mut player = MoviePlayer::new(movie, &mut emu).unwrap();
+		let mut result = Ok(Some(0));
+		while let Ok(Some(_)) = result {
+			result = player.play_frame(&mut emu);
+		}
+		match result {
+			Err(MovieError::Desync(60)) => {},
+			other => panic!("Unexpected result: {:?}", other.map_err(|e| e.to_string()))
+		}
+	}
+}
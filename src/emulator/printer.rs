@@ -0,0 +1,308 @@
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use png;
+
+use emulator::SerialDevice;
+
+// Each data packet holds (up to) two rows of 20 tiles
+const WIDTH: usize = 160;
+const TILES_PER_ROW: usize = WIDTH/8;
+// The printer's RAM only fits 9 data packets (one screen's worth)
+const MAX_DATA: usize = 9*2*TILES_PER_ROW*16;
+// How many status packets say the printer is busy after it is told to print
+const BUSY_STATUS_PACKETS: u8 = 2;
+// Greyscale values for each shade (0 is white)
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+// Status bits
+const CHECKSUM_ERROR: u8 = 1;
+const BUSY: u8 = 2;
+const DATA_FULL: u8 = 4;
+const UNPROCESSED_DATA: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+	Magic1,
+	Magic2,
+	Command,
+	Compression,
+	LengthLow,
+	LengthHigh,
+	Data,
+	ChecksumLow,
+	ChecksumHigh,
+	KeepAlive,
+	Status
+}
+
+// A Game Boy Printer
+// Every packet the game sends looks like 88 33 <command> <compression> <length (2 bytes)> <data>
+// <checksum (2 bytes)> 00 00, and the printer answers the last two bytes with 81 and its status
+// Each printout is saved as a PNG in the printer's directory once the paper is fed out
+pub struct Printer {
+	dir:			PathBuf,
+	state:			State,
+	command:		u8,
+	compressed:		bool,
+	length:			usize,
+	packet:			Vec<u8>,
+	checksum:		u16,
+	status:			u8,
+	busy_packets:	u8,
+	// Tile data waiting to be printed
+	data:			Vec<u8>,
+	// Shades (0-3) of everything printed since the paper was last fed out, WIDTH to a row
+	paper:			Vec<u8>,
+	printouts:		Vec<PathBuf>
+}
+
+impl Printer {
+	pub fn new<P: AsRef<Path>>(dir: P) -> Printer {
+		Printer {
+			dir: dir.as_ref().to_path_buf(),
+			state: State::Magic1,
+			command: 0,
+			compressed: false,
+			length: 0,
+			packet: Vec::new(),
+			checksum: 0,
+			status: 0,
+			busy_packets: 0,
+			data: Vec::new(),
+			paper: Vec::new(),
+			printouts: Vec::new()
+		}
+	}
+	// Paths of every PNG saved so far
+	pub fn printouts(&self) -> &[PathBuf] {
+		&self.printouts
+	}
+	fn receive(&mut self, val: u8) -> u8 {
+		let (next, reply) = match self.state {
+			State::Magic1 => (if val == 0x88 {State::Magic2} else {State::Magic1}, 0),
+			State::Magic2 => (if val == 0x33 {State::Command} else {State::Magic1}, 0),
+			State::Command => {
+				self.command = val;
+				self.checksum = val as u16;
+				(State::Compression, 0)
+			},
+			State::Compression => {
+				self.compressed = val & 1 > 0;
+				self.checksum = self.checksum.wrapping_add(val as u16);
+				(State::LengthLow, 0)
+			},
+			State::LengthLow => {
+				self.length = val as usize;
+				self.checksum = self.checksum.wrapping_add(val as u16);
+				(State::LengthHigh, 0)
+			},
+			State::LengthHigh => {
+				self.length |= (val as usize) << 8;
+				self.checksum = self.checksum.wrapping_add(val as u16);
+				self.packet.clear();
+				(if self.length > 0 {State::Data} else {State::ChecksumLow}, 0)
+			},
+			State::Data => {
+				self.packet.push(val);
+				self.checksum = self.checksum.wrapping_add(val as u16);
+				(if self.packet.len() < self.length {State::Data} else {State::ChecksumLow}, 0)
+			},
+			State::ChecksumLow => {
+				self.checksum ^= val as u16;
+				(State::ChecksumHigh, 0)
+			},
+			State::ChecksumHigh => {
+				self.checksum ^= (val as u16) << 8;
+				if self.checksum == 0 {
+					self.status &= !CHECKSUM_ERROR;
+					self.run_command();
+				} else {
+					self.status |= CHECKSUM_ERROR;
+				}
+				(State::KeepAlive, 0)
+			},
+			State::KeepAlive => (State::Status, 0x81),
+			State::Status => (State::Magic1, self.status)
+		};
+		self.state = next;
+		reply
+	}
+	fn run_command(&mut self) {
+		match self.command {
+			0x01 => {
+				self.data.clear();
+				self.status = 0;
+				self.busy_packets = 0;
+			},
+			0x02 if self.packet.len() >= 4 => {
+				let (margins, palette) = (self.packet[1], self.packet[2]);
+				self.print(palette);
+				self.status = (self.status & !(UNPROCESSED_DATA | DATA_FULL)) | BUSY;
+				self.busy_packets = BUSY_STATUS_PACKETS;
+				// The paper only gets fed out (and saved) if there is a margin after the image
+				if margins & 0xF > 0 {
+					// There isn't any good way to tell the game the printout couldn't be saved
+					let _ = self.feed_paper();
+				}
+			},
+			0x04 => {
+				let packet = ::std::mem::replace(&mut self.packet, Vec::new());
+				if self.compressed {
+					decompress(&packet, &mut self.data);
+				} else {
+					self.data.extend_from_slice(&packet);
+				}
+				self.data.truncate(MAX_DATA);
+				if !self.data.is_empty() {
+					self.status |= UNPROCESSED_DATA;
+				}
+				if self.data.len() == MAX_DATA {
+					self.status |= DATA_FULL;
+				}
+			},
+			0x0F => {
+				if self.busy_packets > 0 {
+					self.busy_packets -= 1;
+					if self.busy_packets == 0 {
+						self.status &= !BUSY;
+					}
+				}
+			},
+			_ => {}
+		}
+	}
+	// Moves the tile data onto the paper
+	fn print(&mut self, palette: u8) {
+		// A palette of 0 is treated as the usual 0xE4 (3, 2, 1, 0)
+		let palette = if palette == 0 {0xE4} else {palette};
+		let tile_rows = self.data.len()/(16*TILES_PER_ROW);
+		for tile_row in 0..tile_rows {
+			for line in 0..8 {
+				for x in 0..WIDTH {
+					let tile = &self.data[(tile_row*TILES_PER_ROW + x/8)*16..];
+					let bit = 7 - x%8;
+					let color = ((tile[2*line] >> bit) & 1) | (((tile[2*line + 1] >> bit) & 1) << 1);
+					self.paper.push((palette >> (2*color)) & 3);
+				}
+			}
+		}
+		self.data.clear();
+	}
+	fn feed_paper(&mut self) -> io::Result<()> {
+		if self.paper.is_empty() {
+			return Ok(());
+		}
+		let paper = ::std::mem::replace(&mut self.paper, Vec::new());
+		fs::create_dir_all(&self.dir)?;
+		// Printouts from earlier sessions are kept, so use the first name that isn't taken yet
+		let path = (self.printouts.len()..).map(|i| self.dir.join(format!("print_{}.png", i)))
+										   .find(|path| !path.exists()).unwrap();
+
+		let mut encoder = png::Encoder::new(BufWriter::new(File::create(&path)?), WIDTH as u32, (paper.len()/WIDTH) as u32);
+		encoder.set_color(png::ColorType::Grayscale);
+		encoder.set_depth(png::BitDepth::Eight);
+		let pixels = paper.iter().map(|&shade| SHADES[shade as usize]).collect::<Vec<_>>();
+		encoder.write_header()
+			   .and_then(|mut writer| writer.write_image_data(&pixels))
+			   .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+		self.printouts.push(path);
+		Ok(())
+	}
+}
+
+impl SerialDevice for Printer {
+	fn exchange(&mut self, out: u8) -> u8 {
+		self.receive(out)
+	}
+}
+
+// Expands the printer's run-length encoding
+// A control byte with the top bit set means repeat the next byte (control & 0x7F) + 2 times,
+// otherwise the next control + 1 bytes are copied as-is
+fn decompress(packet: &[u8], out: &mut Vec<u8>) {
+	let mut i = 0;
+	while i < packet.len() {
+		let control = packet[i];
+		i += 1;
+		if control & 0x80 > 0 {
+			if let Some(&val) = packet.get(i) {
+				out.extend((0..(control & 0x7F) as usize + 2).map(|_| val));
+			}
+			i += 1;
+		} else {
+			let end = (i + control as usize + 1).min(packet.len());
+			out.extend_from_slice(&packet[i..end]);
+			i = end;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::env;
+
+	// Sends a whole packet, returning the printer's last two replies
+	fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+		let mut bytes = vec![0x88, 0x33, command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+		bytes.extend_from_slice(data);
+		let checksum = bytes[2..].iter().fold(0u16, |acc, &b| acc.wrapping_add(b as u16));
+		bytes.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8, 0, 0]);
+
+		let replies = bytes.iter().map(|&b| printer.exchange(b)).collect::<Vec<_>>();
+		assert!(replies[..replies.len() - 2].iter().all(|&r| r == 0));
+		(replies[replies.len() - 2], replies[replies.len() - 1])
+	}
+
+	#[test]
+	fn test_decompress() {
+		let mut out = Vec::new();
+		decompress(&[0x81, 0xAB, 0x01, 0x01, 0x02], &mut out);
+		assert_eq!(out, vec![0xAB, 0xAB, 0xAB, 0x01, 0x02]);
+	}
+	#[test]
+	fn test_print() {
+		let dir = env::temp_dir().join(format!("rgb_printer_test_{}", ::std::process::id()));
+		let mut printer = Printer::new(&dir);
+		assert_eq!(send(&mut printer, 0x01, false, &[]), (0x81, 0));
+
+		// One packet of solid black (color 3) and one of white (color 0), compressed
+		assert_eq!(send(&mut printer, 0x04, false, &[0xFF; 640]), (0x81, UNPROCESSED_DATA));
+		assert_eq!(send(&mut printer, 0x04, true, &[0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFA, 0x00]),
+				   (0x81, UNPROCESSED_DATA));
+		assert_eq!(send(&mut printer, 0x04, false, &[]), (0x81, UNPROCESSED_DATA));
+
+		assert_eq!(send(&mut printer, 0x02, false, &[1, 0x13, 0xE4, 0x40]), (0x81, BUSY));
+		assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, BUSY));
+		assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, 0));
+
+		assert_eq!(printer.printouts().len(), 1);
+		let decoder = png::Decoder::new(File::open(&printer.printouts()[0]).unwrap());
+		let mut reader = decoder.read_info().unwrap();
+		let mut pixels = vec![0; reader.output_buffer_size()];
+		reader.next_frame(&mut pixels).unwrap();
+		assert_eq!((reader.info().width, reader.info().height), (160, 32));
+		assert!(pixels[..160*16].iter().all(|&p| p == 0x00));
+		assert!(pixels[160*16..].iter().all(|&p| p == 0xFF));
+
+		// A new session doesn't overwrite what was printed before
+		let mut printer = Printer::new(&dir);
+		send(&mut printer, 0x04, false, &[0xFF; 640]);
+		send(&mut printer, 0x02, false, &[1, 0x13, 0xE4, 0x40]);
+		send(&mut printer, 0x0F, false, &[]);
+		assert_eq!(printer.printouts(), &[dir.join("print_1.png")]);
+		let _ = fs::remove_dir_all(&dir);
+	}
+	#[test]
+	fn test_checksum_error() {
+		let mut printer = Printer::new(env::temp_dir());
+		let replies = [0x88, 0x33, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00].iter()
+			.map(|&b| printer.exchange(b)).collect::<Vec<_>>();
+		assert_eq!(&replies[8..], &[0x81, CHECKSUM_ERROR]);
+	}
+}
@@ -1,4 +1,8 @@
 use std::fmt;
+use std::io;
+use std::io::{Write, Read};
+
+use emulator::serialize::*;
 
 /** Gameboy's 8-bit registers
 		In order, F A C B E D L H **/
@@ -32,6 +36,17 @@ impl Registers {
 	pub fn new() -> Registers {
 	    Registers{mem: [0; 8], pc: 0, sp: 0}
 	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_bytes(out, &self.mem)?;
+		write_u16(out, self.pc)?;
+		write_u16(out, self.sp)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		read_bytes(inp, &mut self.mem)?;
+		self.pc = read_u16(inp)?;
+		self.sp = read_u16(inp)?;
+		Ok(())
+	}
 	//Register access
 	pub fn a(&mut self) -> &mut u8 {
 		&mut self.mem[1]
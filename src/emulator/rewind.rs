@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+use emulator::Emulator;
+
+// Keeps a rolling history of save states so emulation can be stepped backwards
+//
+// Only the newest snapshot is kept whole. Each older one is stored as the difference from the
+// snapshot after it (XORed, with the runs of zeros left over squeezed out), which is small since
+// not much changes in a few frames.
+pub struct Rewinder {
+	frames_per_snapshot:	u32,
+	max_snapshots:			usize,
+	frames:					u32,
+	newest:					Option<Vec<u8>>,
+	// Oldest first
+	deltas:					VecDeque<Vec<u8>>
+}
+
+impl Rewinder {
+	// Takes a snapshot every frames_per_snapshot frames, keeping at most max_snapshots of them
+	pub fn new(frames_per_snapshot: u32, max_snapshots: usize) -> Rewinder {
+		Rewinder {
+			frames_per_snapshot: frames_per_snapshot.max(1),
+			max_snapshots: max_snapshots.max(1),
+			frames: 0,
+			newest: None,
+			deltas: VecDeque::new()
+		}
+	}
+	// Should be called once after every emulated frame
+	pub fn record(&mut self, emu: &Emulator) {
+		self.frames += 1;
+		if self.frames < self.frames_per_snapshot {
+			return;
+		}
+		self.frames = 0;
+
+		let state = emu.export_state();
+		if let Some(newest) = self.newest.take() {
+			if newest.len() == state.len() {
+				self.deltas.push_back(delta(&state, &newest));
+			} else {
+				// Nothing older can be reconstructed from a snapshot of a different size
+				self.deltas.clear();
+			}
+		}
+		self.newest = Some(state);
+		while self.len() > self.max_snapshots {
+			self.deltas.pop_front();
+		}
+	}
+	// Loads the newest snapshot (and forgets it, so the next call goes further back)
+	// Returns false if there is nothing left to rewind to
+	pub fn rewind(&mut self, emu: &mut Emulator) -> bool {
+		let state = match self.newest.take() {
+			Some(state) => state,
+			None => return false
+		};
+		self.newest = self.deltas.pop_back().map(|delta| apply_delta(&state, &delta));
+		self.frames = 0;
+		emu.import_state(&state).is_ok()
+	}
+	// Number of snapshots that can be rewound to
+	pub fn len(&self) -> usize {
+		self.newest.as_ref().map_or(0, |_| 1 + self.deltas.len())
+	}
+	pub fn is_empty(&self) -> bool {
+		self.newest.is_none()
+	}
+	pub fn clear(&mut self) {
+		self.newest = None;
+		self.deltas.clear();
+		self.frames = 0;
+	}
+	// Roughly how many bytes the history takes up
+	pub fn memory_usage(&self) -> usize {
+		self.newest.as_ref().map_or(0, |state| state.len()) + self.deltas.iter().map(|delta| delta.len()).sum::<usize>()
+	}
+}
+
+// Encodes old as a difference from new (which must be the same length)
+// The result is a list of chunks: a 2-byte count of unchanged bytes, a 2-byte count of changed bytes
+// and then the changed bytes (XORed with new)
+fn delta(new: &[u8], old: &[u8]) -> Vec<u8> {
+	let mut out = Vec::new();
+	let mut i = 0;
+	while i < new.len() {
+		let start = i;
+		while i < new.len() && i - start < 0xFFFF && new[i] == old[i] {
+			i += 1;
+		}
+		let same = i - start;
+
+		let start = i;
+		while i < new.len() && i - start < 0xFFFF && new[i] != old[i] {
+			i += 1;
+		}
+		out.extend_from_slice(&[same as u8, (same >> 8) as u8, (i - start) as u8, ((i - start) >> 8) as u8]);
+		out.extend(new[start..i].iter().zip(&old[start..i]).map(|(a, b)| a ^ b));
+	}
+	out
+}
+
+fn apply_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+	let mut old = new.to_vec();
+	let (mut i, mut pos) = (0, 0);
+	while i + 4 <= delta.len() {
+		let same = delta[i] as usize | (delta[i + 1] as usize) << 8;
+		let changed = delta[i + 2] as usize | (delta[i + 3] as usize) << 8;
+		i += 4;
+		pos += same;
+		for (byte, &diff) in old[pos..pos + changed].iter_mut().zip(&delta[i..i + changed]) {
+			*byte ^= diff;
+		}
+		i += changed;
+		pos += changed;
+	}
+	old
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_delta() {
+		let old = (0..100000).map(|i| (i%251) as u8).collect::<Vec<_>>();
+		let mut new = old.clone();
+		new[5] = 0;
+		new[70000] = 1;
+		for byte in new[80000..].iter_mut() {
+			*byte = 0xAA;
+		}
+		let delta = delta(&new, &old);
+		assert!(delta.len() < 30000);
+		assert_eq!(apply_delta(&new, &delta), old);
+	}
+	#[test]
+	fn test_rewind() {
+		let mut rom = vec![0; 0x8000];
+		// Count up in RAM forever
+		let code = [0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD];
+		rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+		let mut emu = Emulator::new();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.skip_bios();
+
+		let mut rewinder = Rewinder::new(2, 3);
+		let mut states = Vec::new();
+		for _ in 0..10 {
+			emu.run_frame();
+			rewinder.record(&emu);
+			states.push(emu.export_state());
+		}
+		assert_eq!(rewinder.len(), 3);
+
+		// Snapshots were taken after frames 2, 4, ..., 10 but only the last 3 are left
+		for &frame in &[10, 8, 6] {
+			assert!(rewinder.rewind(&mut emu));
+			assert_eq!(emu.export_state(), states[frame - 1]);
+		}
+		assert!(!rewinder.rewind(&mut emu));
+		assert_eq!(emu.export_state(), states[5]);
+	}
+}
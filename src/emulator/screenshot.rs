@@ -0,0 +1,218 @@
+use std::io;
+use std::fmt;
+use std::fs::File;
+use std::io::{Write, Read, BufWriter, BufReader};
+use std::path::Path;
+
+use png;
+
+use emulator::Color;
+use emulator::serialize::hash_bytes;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// The screen as 8-bit RGB, row by row
+// Monochrome shades are drawn in grey rather than any particular palette
+pub fn screen_to_rgb(screen: &[[Color; SCREEN_WIDTH]; SCREEN_HEIGHT]) -> Vec<u8> {
+	let mut rgb = Vec::with_capacity(3*SCREEN_WIDTH*SCREEN_HEIGHT);
+	for &color in screen.iter().flat_map(|row| row.iter()) {
+		rgb.extend_from_slice(&match color {
+			Color::CGB(red, green, blue) => [red, green, blue],
+			_ => {
+				let shade = color.to_f32().unwrap() as u8;
+				[shade, shade, shade]
+			}
+		});
+	}
+	rgb
+}
+
+// Identifies what is on screen, so two runs can be compared without saving any images
+pub fn screen_hash(screen: &[[Color; SCREEN_WIDTH]; SCREEN_HEIGHT]) -> u64 {
+	hash_bytes(&screen_to_rgb(screen))
+}
+
+pub fn write_png<W: Write>(screen: &[[Color; SCREEN_WIDTH]; SCREEN_HEIGHT], out: W) -> io::Result<()> {
+	let mut encoder = png::Encoder::new(out, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+	encoder.set_color(png::ColorType::Rgb);
+	encoder.set_depth(png::BitDepth::Eight);
+	encoder.write_header()
+		   .and_then(|mut writer| writer.write_image_data(&screen_to_rgb(screen)))
+		   .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+pub fn save_png<P: AsRef<Path>>(screen: &[[Color; SCREEN_WIDTH]; SCREEN_HEIGHT], path: P) -> io::Result<()> {
+	write_png(screen, BufWriter::new(File::create(path)?))
+}
+
+// Reads a screenshot (e.g. one saved by save_png) back as 8-bit RGB
+pub fn read_png<R: Read>(inp: R) -> io::Result<Vec<u8>> {
+	let to_io = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+	let mut decoder = png::Decoder::new(inp);
+	decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+	let mut reader = decoder.read_info().map_err(to_io)?;
+	let mut pixels = vec![0; reader.output_buffer_size()];
+	let info = reader.next_frame(&mut pixels).map_err(to_io)?;
+	if (info.width, info.height) != (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32) {
+		return Err(io::Error::new(io::ErrorKind::InvalidData,
+								  format!("image is {}x{} instead of {}x{}", info.width, info.height, SCREEN_WIDTH, SCREEN_HEIGHT)));
+	}
+	let channels = info.color_type.samples();
+	Ok(pixels[..info.buffer_size()].chunks(channels).flat_map(|pixel| match channels {
+		// Greyscale (with or without alpha)
+		1 | 2 => vec![pixel[0]; 3],
+		_ => pixel[..3].to_vec()
+	}).collect())
+}
+
+pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+	read_png(BufReader::new(File::open(path)?))
+}
+
+// How much the screen differs from a reference image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenDiff {
+	// Pixels with any channel differing by more than the tolerance they were compared with
+	pub differing_pixels:	usize,
+	// The biggest difference in any one channel of any pixel
+	pub max_difference:		u8,
+	// Smallest (left, top, right, bottom) rectangle containing every differing pixel
+	pub bounds:				Option<(usize, usize, usize, usize)>,
+	// Differing pixels are red in this (RGB) image, and everything else is a faded copy of the screen
+	pub diff_image:			Vec<u8>
+}
+
+impl ScreenDiff {
+	pub fn matches(&self) -> bool {
+		self.differing_pixels == 0
+	}
+	pub fn save_diff_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+		encoder.set_color(png::ColorType::Rgb);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.write_header()
+			   .and_then(|mut writer| writer.write_image_data(&self.diff_image))
+			   .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+	}
+}
+
+impl fmt::Display for ScreenDiff {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.bounds {
+			None => write!(f, "screens match (max channel difference {})", self.max_difference),
+			Some((left, top, right, bottom)) => write!(f, "{} of {} pixels differ (max channel difference {}) between ({}, {}) and ({}, {})",
+													  self.differing_pixels, SCREEN_WIDTH*SCREEN_HEIGHT, self.max_difference,
+													  left, top, right, bottom)
+		}
+	}
+}
+
+// Compares the screen against a reference image (8-bit RGB, as returned by read_png)
+// Pixels only count as different if some channel differs by more than tolerance (or if the
+// reference doesn't have them at all)
+pub fn compare_screen(screen: &[[Color; SCREEN_WIDTH]; SCREEN_HEIGHT], reference: &[u8], tolerance: u8) -> ScreenDiff {
+	let actual = screen_to_rgb(screen);
+	let mut diff = ScreenDiff {
+		differing_pixels: 0,
+		max_difference: 0,
+		bounds: None,
+		diff_image: Vec::with_capacity(actual.len())
+	};
+	for (i, pixel) in actual.chunks(3).enumerate() {
+		// Pixels missing from a reference that's too short are as different as can be
+		let difference = match reference.get(3*i..3*i + 3) {
+			Some(expected) => pixel.iter().zip(expected).map(|(&a, &b)| (a as i16 - b as i16).abs() as u8).max().unwrap_or(0),
+			None => 0xFF
+		};
+		diff.max_difference = diff.max_difference.max(difference);
+		if difference <= tolerance {
+			diff.diff_image.extend(pixel.iter().map(|&c| 0xC0 + c/4));
+			continue;
+		}
+
+		diff.differing_pixels += 1;
+		diff.diff_image.extend_from_slice(&[0xFF, 0, 0]);
+		let (x, y) = (i%SCREEN_WIDTH, i/SCREEN_WIDTH);
+		diff.bounds = Some(match diff.bounds {
+			None => (x, y, x, y),
+			Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x), bottom.max(y))
+		});
+	}
+	diff
+}
+
+// Compares the screen against a PNG saved earlier (e.g. a golden image kept alongside a test)
+pub fn compare_with_png<P: AsRef<Path>>(screen: &[[Color; SCREEN_WIDTH]; SCREEN_HEIGHT], path: P,
+										 tolerance: u8) -> io::Result<ScreenDiff> {
+	load_png(path).map(|reference| compare_screen(screen, &reference, tolerance))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_write_png() {
+		let mut screen = [[Color::WHITE; SCREEN_WIDTH]; SCREEN_HEIGHT];
+		screen[10][20] = Color::BLACK;
+		screen[143][159] = Color::CGB(1, 2, 3);
+		let hash = screen_hash(&screen);
+		screen[0][0] = Color::LIGHT_GRAY;
+		assert!(screen_hash(&screen) != hash);
+
+		let mut out = Vec::new();
+		write_png(&screen, &mut out).unwrap();
+		let mut reader = png::Decoder::new(&out[..]).read_info().unwrap();
+		let mut pixels = vec![0; reader.output_buffer_size()];
+		reader.next_frame(&mut pixels).unwrap();
+		assert_eq!(pixels, screen_to_rgb(&screen));
+		assert_eq!(&pixels[..6], &[192, 192, 192, 255, 255, 255]);
+		assert_eq!(&pixels[3*(10*SCREEN_WIDTH + 20)..][..3], &[0, 0, 0]);
+		assert_eq!(&pixels[pixels.len() - 3..], &[1, 2, 3]);
+		assert_eq!(read_png(&out[..]).unwrap(), pixels);
+	}
+	#[test]
+	fn test_compare() {
+		let mut screen = [[Color::CGB(100, 100, 100); SCREEN_WIDTH]; SCREEN_HEIGHT];
+		let reference = screen_to_rgb(&screen);
+		assert!(compare_screen(&screen, &reference, 0).matches());
+
+		screen[5][7] = Color::CGB(104, 100, 100);
+		screen[20][3] = Color::CGB(100, 90, 100);
+		let diff = compare_screen(&screen, &reference, 0);
+		assert_eq!((diff.differing_pixels, diff.max_difference, diff.bounds), (2, 10, Some((3, 5, 7, 20))));
+		assert_eq!(&diff.diff_image[3*(5*SCREEN_WIDTH + 7)..][..3], &[0xFF, 0, 0]);
+		assert_eq!(diff.to_string(), "2 of 23040 pixels differ (max channel difference 10) between (3, 5) and (7, 20)");
+
+		let diff = compare_screen(&screen, &reference, 5);
+		assert_eq!((diff.differing_pixels, diff.bounds), (1, Some((3, 20, 3, 20))));
+		assert!(compare_screen(&screen, &reference, 10).matches());
+
+		// Everything past the end of a short reference differs
+		let diff = compare_screen(&screen, &reference[..3*SCREEN_WIDTH + 4], 10);
+		assert_eq!((diff.differing_pixels, diff.max_difference), (SCREEN_WIDTH*(SCREEN_HEIGHT - 1) - 1, 0xFF));
+		assert_eq!(diff.bounds, Some((0, 1, SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1)));
+		assert_eq!(diff.diff_image.len(), 3*SCREEN_WIDTH*SCREEN_HEIGHT);
+	}
+	#[test]
+	fn test_read_png() {
+		// Greyscale images (like the printer's) are read as RGB
+		let mut out = Vec::new();
+		{
+			let mut encoder = png::Encoder::new(&mut out, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+			encoder.set_color(png::ColorType::Grayscale);
+			encoder.set_depth(png::BitDepth::Eight);
+			encoder.write_header().unwrap().write_image_data(&[0x55; SCREEN_WIDTH*SCREEN_HEIGHT]).unwrap();
+		}
+		assert_eq!(read_png(&out[..]).unwrap(), vec![0x55; 3*SCREEN_WIDTH*SCREEN_HEIGHT]);
+
+		let mut out = Vec::new();
+		{
+			let mut encoder = png::Encoder::new(&mut out, 8, 8);
+			encoder.set_color(png::ColorType::Grayscale);
+			encoder.write_header().unwrap().write_image_data(&[0; 64]).unwrap();
+		}
+		assert!(read_png(&out[..]).is_err());
+	}
+}
@@ -0,0 +1,251 @@
+use std::io;
+use std::io::{Write, Read};
+
+use emulator::serialize::*;
+
+// The internal clock runs at 8192 Hz (4194304/8192 = 512 cycles a bit)
+const CYCLES_PER_BIT: i32 = 512;
+// CGB fast mode runs it at 262144 Hz instead
+const CYCLES_PER_BIT_FAST: i32 = 16;
+// Only the end of a long-winded console is kept around
+const MAX_CONSOLE_LENGTH: usize = 0x10000;
+
+// Whatever is plugged into the other end of the link cable
+pub trait SerialDevice {
+	// Called when this Gameboy starts a transfer on its internal clock
+	// Takes the byte being sent and returns the byte shifted back in
+	fn exchange(&mut self, out: u8) -> u8;
+	// Polled while this Gameboy waits for the other end to clock a transfer
+	// If it has, `out` is sent to it and the byte it sent is returned
+	fn external_clock(&mut self, _out: u8) -> Option<u8> {
+		None
+	}
+	// Called every step (whether or not a transfer is happening) with the number of cycles that passed
+	fn step(&mut self, _cycles: i16) {}
+}
+
+// What's on the other end when nothing is: the line reads all 1s and there is never an external clock
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+	fn exchange(&mut self, _out: u8) -> u8 {
+		0xFF
+	}
+}
+
+// Collects every byte sent over the port as text
+// Test ROMs (like Blargg's) and debug builds of games print their output this way
+pub struct SerialConsole {
+	text:	String,
+	unread:	usize	//Index into text of the first byte not yet taken
+}
+
+impl SerialConsole {
+	pub fn new() -> SerialConsole {
+		SerialConsole{text: String::new(), unread: 0}
+	}
+	fn push(&mut self, byte: u8) {
+		if self.text.len() >= MAX_CONSOLE_LENGTH {
+			let extra = self.text.len() - MAX_CONSOLE_LENGTH/2;
+			let extra = (extra..self.text.len()).find(|&i| self.text.is_char_boundary(i)).unwrap_or(extra);
+			self.text.drain(..extra);
+			self.unread = self.unread.saturating_sub(extra);
+		}
+		self.text.push(byte as char);
+	}
+	// Everything sent so far
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+	// Everything sent since the last time this was called
+	pub fn take_new(&mut self) -> String {
+		let new = self.text[self.unread..].to_string();
+		self.unread = self.text.len();
+		new
+	}
+	pub fn clear(&mut self) {
+		self.text.clear();
+		self.unread = 0;
+	}
+}
+
+// Serial port (handles 0xFF01-0xFF02)
+pub struct Serial {
+	sb:			u8,
+	sc:			u8,
+	// Byte coming in from the device, shifted into sb a bit at a time
+	incoming:	u8,
+	bits_left:	u8,
+	timer:		i32,
+	device:		Box<SerialDevice>,
+	pub console:SerialConsole
+}
+
+impl Serial {
+	pub fn new() -> Serial {
+		Serial {
+			sb: 0,
+			sc: 0,
+			incoming: 0xFF,
+			bits_left: 0,
+			timer: 0,
+			device: Box::new(Disconnected),
+			console: SerialConsole::new()
+		}
+	}
+	pub fn rb(&self, address: u16, cgb_mode: bool) -> u8 {
+		match address {
+			0xFF01 => self.sb,
+			0xFF02 => self.sc | if cgb_mode {0x7C} else {0x7E},
+			_ => 0xFF
+		}
+	}
+	pub fn wb(&mut self, address: u16, val: u8) {
+		match address {
+			0xFF01 => self.sb = val,
+			0xFF02 => {
+				self.sc = val & 0x83;
+				if self.transferring() && self.internal_clock() {
+					self.console.push(self.sb);
+					self.incoming = self.device.exchange(self.sb);
+					self.bits_left = 8;
+					self.timer = 0;
+				}
+			},
+			_ => {}
+		}
+	}
+	fn transferring(&self) -> bool {
+		self.sc & 0x80 > 0
+	}
+	fn internal_clock(&self) -> bool {
+		self.sc & 1 > 0
+	}
+	// The device on the other end (and the console) aren't part of the state
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_u8(out, self.sb)?;
+		write_u8(out, self.sc)?;
+		write_u8(out, self.incoming)?;
+		write_u8(out, self.bits_left)?;
+		write_i32(out, self.timer)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.sb = read_u8(inp)?;
+		self.sc = read_u8(inp)?;
+		self.incoming = read_u8(inp)?;
+		self.bits_left = read_u8(inp)?.min(8);
+		self.timer = read_i32(inp)?;
+		Ok(())
+	}
+	// Plugs a new device into the port, returning the old one
+	pub fn connect(&mut self, device: Box<SerialDevice>) -> Box<SerialDevice> {
+		::std::mem::replace(&mut self.device, device)
+	}
+	// Returns true if a transfer finished (and the serial interrupt should be requested)
+	pub fn step(&mut self, cycles: i16, cgb_mode: bool) -> bool {
+		self.device.step(cycles);
+		if !self.transferring() {
+			return false;
+		}
+		if !self.internal_clock() {
+			return match self.device.external_clock(self.sb) {
+				Some(val) => {
+					self.console.push(self.sb);
+					self.sb = val;
+					self.finish_transfer()
+				},
+				None => false
+			};
+		}
+
+		let period = if cgb_mode && self.sc & 2 > 0 {CYCLES_PER_BIT_FAST} else {CYCLES_PER_BIT};
+		self.timer += cycles as i32;
+		while self.timer >= period && self.bits_left > 0 {
+			self.timer -= period;
+			self.bits_left -= 1;
+			let bit = (self.incoming >> self.bits_left) & 1;
+			self.sb = (self.sb << 1) | bit;
+		}
+		self.bits_left == 0 && self.finish_transfer()
+	}
+	fn finish_transfer(&mut self) -> bool {
+		self.sc &= 0x7F;
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	struct Echo(Vec<u8>);
+
+	impl SerialDevice for Echo {
+		fn exchange(&mut self, out: u8) -> u8 {
+			self.0.push(out);
+			out.wrapping_add(1)
+		}
+		fn external_clock(&mut self, out: u8) -> Option<u8> {
+			self.0.push(out);
+			Some(0x42)
+		}
+	}
+
+	#[test]
+	fn test_internal_clock() {
+		let mut serial = Serial::new();
+		serial.wb(0xFF01, 0x10);
+		serial.wb(0xFF02, 0x81);
+		assert_eq!(serial.rb(0xFF02, false), 0xFF);
+
+		// Bits come in a bit at a time, most significant first
+		assert!(!serial.step(4*CYCLES_PER_BIT as i16, false));
+		assert_eq!(serial.rb(0xFF01, false), 0x0F);
+		assert!(!serial.step(3*CYCLES_PER_BIT as i16, false));
+		assert!(serial.step(CYCLES_PER_BIT as i16, false));
+		assert_eq!(serial.rb(0xFF01, false), 0xFF);
+		assert_eq!(serial.rb(0xFF02, false), 0x7F);
+		assert!(!serial.step(CYCLES_PER_BIT as i16, false));
+	}
+	#[test]
+	fn test_fast_mode() {
+		let mut serial = Serial::new();
+		serial.connect(Box::new(Echo(Vec::new())));
+		serial.wb(0xFF01, 0x10);
+		serial.wb(0xFF02, 0x83);
+		assert!(serial.step(8*CYCLES_PER_BIT_FAST as i16, true));
+		assert_eq!(serial.rb(0xFF01, true), 0x11);
+
+		// Fast mode is CGB only
+		serial.wb(0xFF02, 0x83);
+		assert!(!serial.step(8*CYCLES_PER_BIT_FAST as i16, false));
+	}
+	#[test]
+	fn test_external_clock() {
+		let mut serial = Serial::new();
+		serial.wb(0xFF01, 0x10);
+		serial.wb(0xFF02, 0x80);
+		assert!(!serial.step(8*CYCLES_PER_BIT as i16, false));
+
+		serial.connect(Box::new(Echo(Vec::new())));
+		assert!(serial.step(4, false));
+		assert_eq!(serial.rb(0xFF01, false), 0x42);
+		assert_eq!(serial.rb(0xFF02, false) & 0x80, 0);
+	}
+	#[test]
+	fn test_console() {
+		let mut serial = Serial::new();
+		for &byte in b"Passed\n" {
+			serial.wb(0xFF01, byte);
+			serial.wb(0xFF02, 0x81);
+			serial.step(8*CYCLES_PER_BIT as i16, false);
+		}
+		assert_eq!(serial.console.text(), "Passed\n");
+		assert_eq!(serial.console.take_new(), "Passed\n");
+		assert_eq!(serial.console.take_new(), "");
+
+		// Writing SB alone doesn't send anything
+		serial.wb(0xFF01, b'!');
+		assert_eq!(serial.console.take_new(), "");
+	}
+}
@@ -0,0 +1,89 @@
+// Helpers for writing/reading emulator state in a simple little-endian binary format
+use std::io;
+use std::io::{Write, Read};
+
+pub fn write_u8<W: Write + ?Sized>(w: &mut W, val: u8) -> io::Result<()> {
+	w.write_all(&[val])
+}
+
+pub fn write_u16<W: Write + ?Sized>(w: &mut W, val: u16) -> io::Result<()> {
+	w.write_all(&[val as u8, (val >> 8) as u8])
+}
+
+pub fn write_i16<W: Write + ?Sized>(w: &mut W, val: i16) -> io::Result<()> {
+	write_u16(w, val as u16)
+}
+
+pub fn write_u32<W: Write + ?Sized>(w: &mut W, val: u32) -> io::Result<()> {
+	w.write_all(&[val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8])
+}
+
+pub fn write_i32<W: Write + ?Sized>(w: &mut W, val: i32) -> io::Result<()> {
+	write_u32(w, val as u32)
+}
+
+pub fn write_u64<W: Write + ?Sized>(w: &mut W, val: u64) -> io::Result<()> {
+	write_u32(w, val as u32)?;
+	write_u32(w, (val >> 32) as u32)
+}
+
+pub fn write_bool<W: Write + ?Sized>(w: &mut W, val: bool) -> io::Result<()> {
+	write_u8(w, val as u8)
+}
+
+// Writes the length of the slice followed by its contents
+pub fn write_bytes<W: Write + ?Sized>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+	write_u32(w, bytes.len() as u32)?;
+	w.write_all(bytes)
+}
+
+// 64-bit FNV-1a, for telling games and states apart (not for security)
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xCBF29CE484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001B3))
+}
+
+pub fn read_u8<R: Read + ?Sized>(r: &mut R) -> io::Result<u8> {
+	let mut buf = [0; 1];
+	r.read_exact(&mut buf)?;
+	Ok(buf[0])
+}
+
+pub fn read_u16<R: Read + ?Sized>(r: &mut R) -> io::Result<u16> {
+	let mut buf = [0; 2];
+	r.read_exact(&mut buf)?;
+	Ok(buf[0] as u16 | (buf[1] as u16) << 8)
+}
+
+pub fn read_i16<R: Read + ?Sized>(r: &mut R) -> io::Result<i16> {
+	read_u16(r).map(|val| val as i16)
+}
+
+pub fn read_u32<R: Read + ?Sized>(r: &mut R) -> io::Result<u32> {
+	let mut buf = [0; 4];
+	r.read_exact(&mut buf)?;
+	Ok(buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24)
+}
+
+pub fn read_i32<R: Read + ?Sized>(r: &mut R) -> io::Result<i32> {
+	read_u32(r).map(|val| val as i32)
+}
+
+pub fn read_u64<R: Read + ?Sized>(r: &mut R) -> io::Result<u64> {
+	let lo = read_u32(r)? as u64;
+	let hi = read_u32(r)? as u64;
+	Ok(lo | hi << 32)
+}
+
+pub fn read_bool<R: Read + ?Sized>(r: &mut R) -> io::Result<bool> {
+	read_u8(r).map(|val| val > 0)
+}
+
+// Reads bytes written by write_bytes into buf, which must be the same length as what was written
+pub fn read_bytes<R: Read + ?Sized>(r: &mut R, buf: &mut [u8]) -> io::Result<()> {
+	let len = read_u32(r)? as usize;
+	if len != buf.len() {
+		return Err(io::Error::new(io::ErrorKind::InvalidData,
+			format!("Expected {} bytes of state but found {}", buf.len(), len)));
+	}
+	r.read_exact(buf)
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+
+use emulator::Emulator;
+
+// Blargg's tests write this after their result in cartridge RAM (at 0xA001)
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+// Left at 0xA000 while a Blargg test is still running
+const BLARGG_RUNNING: u8 = 0x80;
+// Mooneye's tests execute LD B,B once they are done...
+const LD_B_B: u8 = 0x40;
+// ...with the start of the Fibonacci sequence in B, C, D, E, H and L if they passed
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+// or 0x42 in all of them if they failed
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+// The test suites whose results can be read automatically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suite {
+	// Blargg's tests print their result over the serial port, or just to the screen (in which
+	// case they also leave it in cartridge RAM)
+	Blargg,
+	// Mooneye GB's tests report through registers
+	Mooneye
+}
+
+impl Suite {
+	// Guesses which suite a ROM belongs to from its path
+	pub fn from_path(path: &str) -> Suite {
+		let path = path.to_lowercase();
+		if path.contains("mooneye") || path.contains("acceptance") || path.contains("emulator-only") {
+			Suite::Mooneye
+		} else {
+			Suite::Blargg
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+	Passed,
+	// Along with whatever the test said went wrong
+	Failed(String),
+	// The test never reported a result
+	TimedOut
+}
+
+impl fmt::Display for Outcome {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Outcome::Passed => write!(f, "passed"),
+			Outcome::Failed(ref reason) if reason.is_empty() => write!(f, "FAILED"),
+			Outcome::Failed(ref reason) => write!(f, "FAILED ({})", reason),
+			Outcome::TimedOut => write!(f, "timed out")
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+	pub name:		String,
+	pub suite:		Suite,
+	pub outcome:	Outcome,
+	// How many frames ran before the result came in
+	pub frames:		u64
+}
+
+// Runs a test ROM (which should already be loaded) until it reports a result or max_frames pass
+pub fn run_test_rom(emu: &mut Emulator, name: &str, suite: Suite, max_frames: u64) -> TestResult {
+	let mut outcome = Outcome::TimedOut;
+	let mut frames = 0;
+	'frames: while frames < max_frames {
+		frames += 1;
+		let mut cycles = 0;
+		while cycles < ::emulator::CYCLES_PER_FRAME*emu.get_speed() {
+			if suite == Suite::Mooneye && emu.rb(emu.get_regs().pc) == LD_B_B {
+				if let Some(result) = mooneye_result(emu) {
+					outcome = result;
+					break 'frames;
+				}
+			}
+			cycles += emu.step().0;
+		}
+		if suite == Suite::Blargg {
+			if let Some(result) = blargg_result(emu) {
+				outcome = result;
+				break;
+			}
+		}
+	}
+	TestResult {
+		name: name.to_string(),
+		suite: suite,
+		outcome: outcome,
+		frames: frames
+	}
+}
+
+fn mooneye_result(emu: &Emulator) -> Option<Outcome> {
+	let regs = emu.get_regs();
+	let (bc, de, hl) = unsafe {(*regs.bc_immut(), *regs.de_immut(), *regs.hl_immut())};
+	let vals = [(bc >> 8) as u8, bc as u8, (de >> 8) as u8, de as u8, (hl >> 8) as u8, hl as u8];
+	if vals == MOONEYE_PASSED {
+		Some(Outcome::Passed)
+	} else if vals == MOONEYE_FAILED {
+		Some(Outcome::Failed(String::new()))
+	} else {
+		None
+	}
+}
+
+fn blargg_result(emu: &Emulator) -> Option<Outcome> {
+	let serial = emu.serial_output();
+	if serial.contains("Passed") {
+		return Some(Outcome::Passed);
+	} else if serial.contains("Failed") {
+		return Some(Outcome::Failed(summarize(serial)));
+	}
+
+	if (0..3).any(|i| emu.rb(0xA001 + i) != BLARGG_SIGNATURE[i as usize]) {
+		return None;
+	}
+	match emu.rb(0xA000) {
+		BLARGG_RUNNING => None,
+		0 => Some(Outcome::Passed),
+		code => {
+			let mut text = String::new();
+			let mut addr = 0xA004;
+			while addr < 0xC000 && emu.rb(addr) != 0 {
+				text.push(emu.rb(addr) as char);
+				addr += 1;
+			}
+			let text = summarize(&text);
+			Some(Outcome::Failed(if text.is_empty() {format!("code {}", code)} else {text}))
+		}
+	}
+}
+
+// Squeezes a test's output onto one line, dropping the test's name (the first line)
+fn summarize(text: &str) -> String {
+	text.lines().skip(1).map(|line| line.trim()).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+// Formats results as a (markdown) table, followed by how many passed
+pub fn report(results: &[TestResult]) -> String {
+	let width = results.iter().map(|result| result.name.len()).max().unwrap_or(0).max(4);
+	let mut out = format!("| {:width$} | Suite   | Frames | Result\n|-{:-<width$}-|---------|--------|-------\n", "Test", "", width = width);
+	for result in results {
+		out += &format!("| {:width$} | {:7} | {:6} | {}\n", result.name, format!("{:?}", result.suite), result.frames,
+						result.outcome, width = width);
+	}
+	let passed = results.iter().filter(|result| result.outcome == Outcome::Passed).count();
+	out += &format!("\n{}/{} passed\n", passed, results.len());
+	out
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn make_emulator(code: &[u8]) -> Emulator {
+		let mut rom = vec![0; 0x8000];
+		// JP 0x150 (to skip over the header)
+		rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+		rom[0x150..0x150 + code.len()].copy_from_slice(code);
+		let mut emu = Emulator::new();
+		emu.load_game_from_bytes(&rom).unwrap();
+		emu.skip_bios();
+		emu
+	}
+
+	#[test]
+	fn test_mooneye() {
+		// LD B,3; LD C,5; LD D,8; LD E,13; LD H,21; LD L,34; LD B,B; JR -2
+		let mut emu = make_emulator(&[0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40, 0x18, 0xFE]);
+		let result = run_test_rom(&mut emu, "fib", Suite::Mooneye, 10);
+		assert_eq!((result.outcome, result.frames), (Outcome::Passed, 1));
+
+		let mut emu = make_emulator(&[0x3E, 0x42, 0x47, 0x4F, 0x57, 0x5F, 0x67, 0x6F, 0x40, 0x18, 0xFE]);
+		assert_eq!(run_test_rom(&mut emu, "fail", Suite::Mooneye, 10).outcome, Outcome::Failed(String::new()));
+
+		let mut emu = make_emulator(&[0x18, 0xFE]);
+		assert_eq!(run_test_rom(&mut emu, "hang", Suite::Mooneye, 10).outcome, Outcome::TimedOut);
+	}
+	#[test]
+	fn test_blargg() {
+		// Send "Passed" over the serial port
+		let mut code = Vec::new();
+		for &c in b"\nPassed" {
+			code.extend_from_slice(&[0x3E, c, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0xF0, 0x02, 0xE6, 0x80, 0x20, 0xFA]);
+		}
+		code.extend_from_slice(&[0x18, 0xFE]);
+		let mut emu = make_emulator(&code);
+		assert_eq!(run_test_rom(&mut emu, "serial", Suite::Blargg, 10).outcome, Outcome::Passed);
+
+		let mut emu = make_emulator(&[0x18, 0xFE]);
+		assert_eq!(run_test_rom(&mut emu, "hang", Suite::Blargg, 10).outcome, Outcome::TimedOut);
+	}
+	#[test]
+	fn test_report() {
+		let results = vec![
+			TestResult{name: "cpu_instrs".to_string(), suite: Suite::Blargg, outcome: Outcome::Passed, frames: 3000},
+			TestResult{name: "div_timing".to_string(), suite: Suite::Mooneye, outcome: Outcome::TimedOut, frames: 60}
+		];
+		let report = report(&results);
+		assert!(report.contains("| cpu_instrs | Blargg  |   3000 | passed\n"));
+		assert!(report.contains("| div_timing | Mooneye |     60 | timed out\n"));
+		assert!(report.ends_with("1/2 passed\n"));
+		assert_eq!(Suite::from_path("roms/mooneye/acceptance/div_timing.gb"), Suite::Mooneye);
+		assert_eq!(Suite::from_path("roms/blargg/cpu_instrs.gb"), Suite::Blargg);
+	}
+}
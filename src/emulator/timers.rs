@@ -1,5 +1,9 @@
+use std::io;
+use std::io::{Write, Read};
+
 use emulator::memory::Memory;
 use emulator::interrupts::InterruptManager;
+use emulator::serialize::*;
 
 pub struct Timers {
 	div_counter:	i16, //Update every 256 cycles (4194304/16384)
@@ -10,6 +14,20 @@ impl Timers {
 	pub fn new() -> Timers {
 	    Timers{div_counter: 0, tima_counter: 0}
 	}
+	pub fn save_state(&self, out: &mut Write) -> io::Result<()> {
+		write_i16(out, self.div_counter)?;
+		write_i16(out, self.tima_counter)
+	}
+	pub fn load_state(&mut self, inp: &mut Read) -> io::Result<()> {
+		self.div_counter = read_i16(inp)?;
+		self.tima_counter = read_i16(inp)?;
+		Ok(())
+	}
+	// Clears DIV and starts it counting again from scratch (STOP does this)
+	pub fn reset_div(&mut self, mem: &mut Memory) {
+		self.div_counter = 256;
+		mem.wb(0xFF04, 0);
+	}
 	pub fn step(&mut self, mem: &mut Memory, im: &InterruptManager, cycles: i16) {
 		self.div_counter -= cycles;
 		if self.div_counter <= 0 {
@@ -23,10 +41,10 @@ impl Timers {
 		}
 		if self.tima_counter <= 0 {
 			self.tima_counter = match tac & 0x3 {
-				0 => (41494304/4096) as i16,
-				1 => (41494304/262144) as i16,
-				2 => (41494304/65536) as i16,
-				3 => (41494304/16384) as i16,
+				0 => (4194304/4096) as i16,
+				1 => (4194304/262144) as i16,
+				2 => (4194304/65536) as i16,
+				3 => (4194304/16384) as i16,
 				_ => panic!("Invalid lower 2 bits for TAC")
 			};
 			
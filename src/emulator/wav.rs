@@ -0,0 +1,106 @@
+use std::io;
+use std::io::{Write, Seek, SeekFrom};
+
+use emulator::Emulator;
+use emulator::SAMPLE_RATE;
+
+const HEADER_SIZE: u32 = 44;
+
+// Writes the emulator's audio to a 16-bit stereo WAV file
+pub struct WavWriter<W: Write + Seek> {
+	out:			W,
+	data_bytes:		u32
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+	pub fn new(mut out: W) -> io::Result<WavWriter<W>> {
+		// The sizes in the header get filled in by finish
+		WavWriter::write_header(&mut out, 0)?;
+		Ok(WavWriter{out: out, data_bytes: 0})
+	}
+	fn write_header(out: &mut W, data_bytes: u32) -> io::Result<()> {
+		let (channels, bits_per_sample) = (2u16, 16u16);
+		let block_align = channels * bits_per_sample/8;
+
+		out.write_all(b"RIFF")?;
+		out.write_all(&u32_bytes(HEADER_SIZE - 8 + data_bytes))?;
+		out.write_all(b"WAVE")?;
+		out.write_all(b"fmt ")?;
+		out.write_all(&u32_bytes(16))?;
+		out.write_all(&u16_bytes(1))?; //PCM
+		out.write_all(&u16_bytes(channels))?;
+		out.write_all(&u32_bytes(SAMPLE_RATE))?;
+		out.write_all(&u32_bytes(SAMPLE_RATE * block_align as u32))?;
+		out.write_all(&u16_bytes(block_align))?;
+		out.write_all(&u16_bytes(bits_per_sample))?;
+		out.write_all(b"data")?;
+		out.write_all(&u32_bytes(data_bytes))
+	}
+	// Takes interleaved stereo samples like those from Emulator::take_audio_samples
+	pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+		let mut bytes = Vec::with_capacity(2*samples.len());
+		for &sample in samples {
+			let sample = (sample.max(-1.0).min(1.0) * i16::max_value() as f32) as i16;
+			bytes.extend_from_slice(&u16_bytes(sample as u16));
+		}
+		self.out.write_all(&bytes)?;
+		self.data_bytes += bytes.len() as u32;
+		Ok(())
+	}
+	// Fills in the header and returns the underlying writer
+	pub fn finish(mut self) -> io::Result<W> {
+		self.out.seek(SeekFrom::Start(0))?;
+		WavWriter::write_header(&mut self.out, self.data_bytes)?;
+		self.out.seek(SeekFrom::End(0))?;
+		self.out.flush()?;
+		Ok(self.out)
+	}
+}
+
+fn u16_bytes(val: u16) -> [u8; 2] {
+	[val as u8, (val >> 8) as u8]
+}
+
+fn u32_bytes(val: u32) -> [u8; 4] {
+	[val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]
+}
+
+// Runs the emulator for the given number of frames, recording everything it plays
+pub fn record_wav<W: Write + Seek>(emu: &mut Emulator, frames: u64, out: W) -> io::Result<W> {
+	let mut wav = WavWriter::new(out)?;
+	// Don't include anything played before recording started
+	emu.take_audio_samples();
+	for _ in 0..frames {
+		emu.run_frame();
+		wav.write_samples(&emu.take_audio_samples())?;
+	}
+	wav.finish()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::io::Cursor;
+
+	#[test]
+	fn test_wav_header() {
+		let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+		wav.write_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+		let data = wav.finish().unwrap().into_inner();
+
+		assert_eq!(data.len(), 44 + 8);
+		assert_eq!(&data[..4], b"RIFF");
+		assert_eq!(&data[4..8], &u32_bytes(36 + 8));
+		assert_eq!(&data[24..28], &u32_bytes(SAMPLE_RATE));
+		assert_eq!(&data[40..44], &u32_bytes(8));
+		assert_eq!(&data[44..48], &[0, 0, 0xFF, 0x7F]);
+	}
+	#[test]
+	fn test_record_frames() {
+		let mut emu = Emulator::default();
+		let data = record_wav(&mut emu, 2, Cursor::new(Vec::new())).unwrap().into_inner();
+		// Every frame has 70224 cycles, and a sample is produced every 64 of them
+		let frames = (2*70224 + 63)/64;
+		assert!(data.len() >= 44 + 4*(frames - 1));
+	}
+}
@@ -0,0 +1,300 @@
+// Runs a game without a window, sound or settings.ini, e.g. for testing on CI machines
+//
+// The exit code says how the run ended: 0 if the stop condition was met (or if there wasn't one),
+// 1 if the frame limit was reached first (or the screen didn't match) and 2 if something went wrong
+extern crate rgb;
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::process;
+
+use rgb::emulator;
+use emulator::{Emulator, LoadError};
+use emulator::screenshot;
+
+const USAGE: &'static str = "\
+Usage: rgb-headless <game> [options]
+
+Options:
+    --bios <path>           Run the BIOS before the game (it is skipped otherwise)
+    --frames <n>            Give up after this many frames (default 3600, one minute)
+    --until-serial <text>   Stop once the game has sent this text over the serial port
+    --until-pc <addr>       Stop once the CPU is about to execute this address
+    --until-mem <addr>=<val>
+                            Stop once the byte at this address has this value
+    --input <path>          Press buttons as described in this input script
+    --screenshot <path>     Save the screen as a PNG when the run ends
+    --compare <path>        Fail unless the screen matches this PNG when the run ends (the differences
+                            are saved next to it, e.g. golden.diff.png)
+    --tolerance <n>         How much each color channel may differ by in --compare (default 0)
+    --print-serial          Print everything sent over the serial port
+
+Numbers may be given in hex with a 0x prefix.
+
+Each line of an input script is a frame number followed by the buttons (right, left, up, down,
+a, b, select or start) to hold from that frame on, e.g. \"120 start\" or \"300\" (release everything).
+Anything after a # is ignored.";
+
+const BUTTONS: [&'static str; 8] = ["right", "left", "up", "down", "a", "b", "select", "start"];
+const DEFAULT_FRAMES: u64 = 3600;
+
+#[derive(Debug, Default)]
+struct Options {
+    game:           String,
+    bios:           Option<String>,
+    frames:         u64,
+    until_serial:   Option<String>,
+    until_pc:       Option<u16>,
+    until_mem:      Option<(u16, u8)>,
+    input:          Option<String>,
+    screenshot:     Option<String>,
+    compare:        Option<String>,
+    tolerance:      u8,
+    print_serial:   bool
+}
+
+impl Options {
+    fn has_condition(&self) -> bool {
+        self.until_serial.is_some() || self.until_pc.is_some() || self.until_mem.is_some()
+    }
+}
+
+fn parse_number(s: &str) -> Result<u64, String> {
+    let result = if s.len() >= 2 && s[..2].to_lowercase() == "0x" {
+        u64::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    };
+    result.map_err(|_| format!("\"{}\" is not a number", s))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    parse_number(s).and_then(|n| if n <= 0xFF {Ok(n as u8)} else {Err(format!("{} does not fit in a byte", s))})
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    parse_number(s).and_then(|n| if n <= 0xFFFF {Ok(n as u16)} else {Err(format!("{} is not an address", s))})
+}
+
+fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options{frames: DEFAULT_FRAMES, ..Options::default()};
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if !options.game.is_empty() {
+                return Err(format!("Unexpected argument \"{}\"", arg));
+            }
+            options.game = arg;
+            continue;
+        }
+        if arg == "--print-serial" {
+            options.print_serial = true;
+            continue;
+        }
+        let val = args.next().ok_or(format!("{} needs a value", arg))?;
+        match arg.as_ref() {
+            "--bios" => options.bios = Some(val),
+            "--frames" => options.frames = parse_number(&val)?,
+            "--until-serial" => options.until_serial = Some(val),
+            "--until-pc" => options.until_pc = Some(parse_address(&val)?),
+            "--until-mem" => {
+                let mut parts = val.splitn(2, '=');
+                let addr = parse_address(parts.next().unwrap_or(""))?;
+                let byte = parse_byte(parts.next().ok_or(format!("--until-mem should look like <addr>=<val>"))?)?;
+                options.until_mem = Some((addr, byte));
+            },
+            "--input" => options.input = Some(val),
+            "--screenshot" => options.screenshot = Some(val),
+            "--compare" => options.compare = Some(val),
+            "--tolerance" => options.tolerance = parse_byte(&val)?,
+            _ => return Err(format!("Unknown option \"{}\"", arg))
+        }
+    }
+    if options.game.is_empty() {
+        return Err("No game given".to_string());
+    }
+    Ok(options)
+}
+
+// Returns the frames the buttons change on (in order) along with what they change to
+fn parse_input_script(script: &str) -> Result<Vec<(u64, u8)>, String> {
+    let mut changes: Vec<(u64, u8)> = Vec::new();
+    for (num, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let frame = match words.next() {
+            Some(frame) => parse_number(frame).map_err(|e| format!("line {}: {}", num + 1, e))?,
+            None => continue
+        };
+        let mut buttons = 0;
+        for word in words {
+            match BUTTONS.iter().position(|&button| button == word.to_lowercase()) {
+                Some(i) => buttons |= 1 << i,
+                None => return Err(format!("line {}: \"{}\" is not a button", num + 1, word))
+            }
+        }
+        if changes.last().map_or(false, |&(last, _)| last > frame) {
+            return Err(format!("line {}: frames must be in order", num + 1));
+        }
+        changes.push((frame, buttons));
+    }
+    Ok(changes)
+}
+
+// Runs the game, returning the number of frames that ran and whether the stop condition was met
+fn run(emu: &mut Emulator, options: &Options, input: &[(u64, u8)]) -> (u64, bool) {
+    let mut next_input = 0;
+    for frame in 0..options.frames {
+        while next_input < input.len() && input[next_input].0 <= frame {
+            emu.set_joypad(input[next_input].1);
+            next_input += 1;
+        }
+
+        let mut cycles = 0;
+        while cycles < emulator::CYCLES_PER_FRAME*emu.get_speed() {
+            cycles += emu.step().0;
+            if options.until_pc.map_or(false, |pc| emu.get_regs().pc == pc) ||
+               options.until_mem.map_or(false, |(addr, val)| emu.rb(addr) == val) {
+                return (frame + 1, true);
+            }
+        }
+
+        let serial = emu.take_serial_output();
+        if options.print_serial {
+            print!("{}", serial);
+        }
+        if let Some(ref text) = options.until_serial {
+            if emu.serial_output().contains(text.as_str()) {
+                return (frame + 1, true);
+            }
+        }
+    }
+    (options.frames, !options.has_condition())
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let input = match options.input {
+        Some(ref path) => {
+            let mut script = String::new();
+            match File::open(path).and_then(|mut file| file.read_to_string(&mut script)).map_err(|e| e.to_string())
+                                  .and_then(|_| parse_input_script(&script)) {
+                Ok(input) => input,
+                Err(e) => {
+                    println!("Could not read input script \"{}\" ({})", path, e);
+                    process::exit(2);
+                }
+            }
+        },
+        None => Vec::new()
+    };
+
+    let mut emu = Emulator::new();
+    match options.bios {
+        Some(ref bios) => if let Err(e) = emu.load_bios(bios.clone()) {
+            println!("Could not load BIOS ({})", e);
+            process::exit(2);
+        },
+        None => emu.skip_bios()
+    }
+    match emu.load_game(options.game.clone()) {
+        Ok(()) => {},
+        Err(e @ LoadError::BadSave(_)) => println!("Running without a save ({})", e),
+        Err(e) => {
+            println!("Could not load \"{}\": {}", options.game, e);
+            process::exit(2);
+        }
+    }
+
+    let (frames, success) = run(&mut emu, &options, &input);
+    let _ = std::io::stdout().flush();
+    println!("frames: {}", frames);
+    println!("pc: {:#06X}", emu.get_regs().pc);
+    println!("screen hash: {:016x}", screenshot::screen_hash(emu.get_screen()));
+    if let Some(ref path) = options.screenshot {
+        if let Err(e) = screenshot::save_png(emu.get_screen(), path) {
+            println!("Could not save screenshot to \"{}\" ({})", path, e);
+            process::exit(2);
+        }
+    }
+    let mut matched = true;
+    if let Some(ref path) = options.compare {
+        match screenshot::compare_with_png(emu.get_screen(), path, options.tolerance) {
+            Ok(diff) => {
+                println!("{}", diff);
+                if !diff.matches() {
+                    matched = false;
+                    let diff_path = match path.rfind('.') {
+                        Some(dot_pos) => format!("{}.diff{}", &path[..dot_pos], &path[dot_pos..]),
+                        None => format!("{}.diff", path)
+                    };
+                    if let Err(e) = diff.save_diff_png(&diff_path) {
+                        println!("Could not save differences to \"{}\" ({})", diff_path, e);
+                    }
+                }
+            },
+            Err(e) => {
+                println!("Could not compare with \"{}\" ({})", path, e);
+                process::exit(2);
+            }
+        }
+    }
+    if !success {
+        println!("Gave up after {} frames", frames);
+    }
+    process::exit(if success && matched {0} else {1});
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(s: &str) -> Result<Options, String> {
+        parse_args(s.split_whitespace().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = args("game.gb --frames 0x10 --until-mem 0xC000=7 --until-serial Passed --tolerance 8").unwrap();
+        assert_eq!(options.game, "game.gb");
+        assert_eq!(options.frames, 16);
+        assert_eq!(options.until_mem, Some((0xC000, 7)));
+        assert_eq!(options.until_serial, Some("Passed".to_string()));
+        assert_eq!(options.tolerance, 8);
+        assert!(options.has_condition());
+        assert!(!args("game.gb").unwrap().has_condition());
+
+        assert!(args("--frames 10").is_err());
+        assert!(args("game.gb --until-mem 0xC000").is_err());
+        assert!(args("game.gb --until-pc 0x10000").is_err());
+        assert!(args("game.gb --frames").is_err());
+        assert!(args("game.gb --fly").is_err());
+    }
+    #[test]
+    fn test_parse_input_script() {
+        let script = "# Skip the title screen\n0\n120 start\n125  # let go\n300 A right\n";
+        assert_eq!(parse_input_script(script), Ok(vec![(0, 0), (120, 0x80), (125, 0), (300, 0x11)]));
+        assert!(parse_input_script("10 jump").is_err());
+        assert!(parse_input_script("10 a\n5 b").is_err());
+    }
+    #[test]
+    fn test_run() {
+        let mut rom = vec![0; 0x8000];
+        // Wait for the A button and then write 0x42 to RAM
+        let code = [0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0xE6, 0x01, 0x20, 0xFA, 0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE];
+        rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+        let mut emu = Emulator::new();
+        emu.load_game_from_bytes(&rom).unwrap();
+        emu.skip_bios();
+
+        let options = args("game.gb --frames 20 --until-mem 0xC000=0x42").unwrap();
+        assert_eq!(run(&mut emu, &options, &parse_input_script("10 a").unwrap()), (11, true));
+        assert_eq!(run(&mut emu, &args("game.gb --frames 5 --until-pc 0x1234").unwrap(), &[]), (5, false));
+    }
+}
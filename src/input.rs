@@ -1,6 +1,8 @@
 use sdl2::EventPump;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod, LCTRLMOD, RCTRLMOD, LSHIFTMOD, RSHIFTMOD};
+
+use std::fs::File;
 
 use utils::*;
 use programstate::*;
@@ -13,15 +15,28 @@ pub fn handle_input(events: &mut EventPump, state: &mut ProgramState, dstate: &m
             Event::Quit{..} => {
                 state.done = true;
             },
-            Event::KeyDown{keycode: key, ..} => {
+            Event::KeyDown{keycode: key, keymod, ..} => {
             	if let Some(key) = key {
-                    handle_keydown(key, state, dstate, emu, dev_keys_enabled, only_gb_buttons);
-                    emu.update_keys(key as u8, true);
+                    if key == Keycode::Backspace && !only_gb_buttons {
+                        state.rewinding = true;
+                        // Anything rewound over would be missing from the movie
+                        state.recording_movie = false;
+                    } else if only_gb_buttons || !handle_save_state_key(key, keymod, state, emu) {
+                        handle_keydown(key, state, dstate, emu, dev_keys_enabled, only_gb_buttons);
+                    }
+                    if !state.playing_movie {
+                        emu.update_keys(key as u8, true);
+                    }
             	}
             },
             Event::KeyUp{keycode: key, ..} => {
                 if let Some(key) = key {
-                    emu.update_keys(key as u8, false);
+                    if key == Keycode::Backspace {
+                        state.rewinding = false;
+                    }
+                    if !state.playing_movie {
+                        emu.update_keys(key as u8, false);
+                    }
                 }
             },
             _ => ()
@@ -29,7 +44,36 @@ pub fn handle_input(events: &mut EventPump, state: &mut ProgramState, dstate: &m
     }
 }
 
-fn handle_keydown(key: Keycode, state: &mut ProgramState, dstate: &mut DebugState, emu: &Emulator, 
+// Ctrl+number saves a state into that numbered slot and Shift+number loads it
+// Returns false if the key wasn't one of these
+fn handle_save_state_key(key: Keycode, keymod: Mod, state: &mut ProgramState, emu: &mut Emulator) -> bool {
+    let slot = match key {
+        Keycode::Num0 => 0, Keycode::Num1 => 1, Keycode::Num2 => 2, Keycode::Num3 => 3, Keycode::Num4 => 4,
+        Keycode::Num5 => 5, Keycode::Num6 => 6, Keycode::Num7 => 7, Keycode::Num8 => 8, Keycode::Num9 => 9,
+        _ => return false
+    };
+    let path = save_state_path(&state.game_path, slot);
+    if keymod.intersects(LCTRLMOD | RCTRLMOD) {
+        match File::create(&path).and_then(|mut file| emu.save_state(&mut file)) {
+            Ok(_) => println!("Saved state to \"{}\"", path),
+            Err(e) => println!("Could not save state to \"{}\" ({})", path, e)
+        }
+    } else if keymod.intersects(LSHIFTMOD | RSHIFTMOD) {
+        match File::open(&path).map_err(|e| e.into()).and_then(|mut file| emu.load_state(&mut file)) {
+            Ok(_) => {
+                println!("Loaded state from \"{}\"", path);
+                // The movie can't jump to a different state partway through
+                state.recording_movie = false;
+            },
+            Err(e) => println!("Could not load state from \"{}\" ({})", path, e)
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+fn handle_keydown(key: Keycode, state: &mut ProgramState, dstate: &mut DebugState, emu: &mut Emulator, 
                     dev_keys_enabled: bool, only_gb_buttons: bool) {
     if only_gb_buttons {
         return state.done = key == Keycode::Escape;
@@ -56,6 +100,18 @@ fn handle_keydown(key: Keycode, state: &mut ProgramState, dstate: &mut DebugStat
         },
         Keycode::R if dev_keys_enabled => {state.debug_regs = !state.debug_regs},
         Keycode::F if dev_keys_enabled => {state.adv_frame = true},
+        Keycode::W if dev_keys_enabled => {state.recording = !state.recording},
+        Keycode::V if dev_keys_enabled && !state.playing_movie => {state.recording_movie = !state.recording_movie},
+        Keycode::O if dev_keys_enabled => {state.debug_audio = !state.debug_audio},
+        Keycode::C if dev_keys_enabled => {state.debug_serial = !state.debug_serial},
+        Keycode::F1 | Keycode::F2 | Keycode::F3 | Keycode::F4 if dev_keys_enabled => {
+            let channel = key as usize - Keycode::F1 as usize;
+            let muted = emu.get_apu().is_channel_muted(channel);
+            emu.get_apu_mut().set_channel_muted(channel, !muted);
+        },
+        Keycode::F5 | Keycode::F6 | Keycode::F7 | Keycode::F8 if dev_keys_enabled => {
+            emu.get_apu_mut().solo_channel(key as usize - Keycode::F5 as usize);
+        },
         Keycode::P => {state.paused = !state.paused},
         Keycode::M if dev_keys_enabled => {
             //Prompt use for range of memory and then dump memory
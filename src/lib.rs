@@ -0,0 +1,8 @@
+//! The emulator core, free of any windowing, rendering or input dependencies.
+//! The SDL/glium frontend in `main.rs` is just one consumer of this library.
+
+extern crate png;
+
+pub mod emulator;
+
+pub use emulator::{Emulator, Event, CYCLES_PER_FRAME, Gpu, Color, Memory, InterruptManager, Timers, Mapper, LoadError, Apu, SAMPLE_RATE, SerialDevice};
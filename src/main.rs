@@ -6,8 +6,9 @@ extern crate sdl2;
 extern crate tini;
 extern crate time;
 extern crate fps_clock;
+extern crate rgb;
 
-mod emulator;
+mod audio;
 mod input;
 mod rendering;
 mod programstate;
@@ -15,12 +16,20 @@ mod utils;
 
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufWriter;
 
 use glium_sdl2::DisplayBuild;
 use tini::Ini;
 use time::PreciseTime;
 
-use emulator::Emulator;
+use rgb::emulator;
+use emulator::{Emulator, LoadError, CYCLES_PER_FRAME};
+use emulator::wav::WavWriter;
+use emulator::link::TcpLink;
+use emulator::printer::Printer;
+use emulator::rewind::Rewinder;
+use emulator::movie::{Movie, MovieRecorder, MoviePlayer};
+use audio::AudioPlayer;
 use input::*;
 use rendering::*;
 use programstate::*;
@@ -28,7 +37,6 @@ use programstate::*;
 const FPS: u32 = 60;
 // A real Gameboy executes this many cycles a second
 const CYCLES_PER_SECOND: u64 = 4194304;
-const CYCLES_PER_FRAME: u64 = CYCLES_PER_SECOND/FPS as u64;
 
 fn main() {
     let mut state = ProgramState::new();
@@ -36,6 +44,7 @@ fn main() {
 
 	let config = Ini::from_file("settings.ini").unwrap();
 	let game_path: String = config.get("system", "game").unwrap();
+    state.game_path = game_path.clone();
     let bios_path: String = config.get("system", "bios").unwrap_or("".to_string());
 	let buttons = ["right", "left", "up", "down", "a", "b", "select", "start"];
 	let controls: Vec<u8> = buttons.iter()
@@ -62,15 +71,91 @@ fn main() {
     let only_gb_buttons = config.get::<String>("debug", "only_gameboy_buttons").map_or(false, |s| {
     	s.to_lowercase() == "true"
     });
+    let print_serial = config.get::<String>("debug", "print_serial").map_or(false, |s| {
+    	s.to_lowercase() == "true"
+    });
+    let sound_enabled = config.get::<String>("sound", "enabled").map_or(true, |s| {
+        s.to_lowercase() == "true"
+    });
+    let sync_to_audio = config.get::<String>("sound", "sync_to_audio").map_or(true, |s| {
+        s.to_lowercase() == "true"
+    });
+    let rewind_enabled = config.get::<String>("rewind", "enabled").map_or(true, |s| {
+        s.to_lowercase() == "true"
+    });
+    let frames_per_snapshot: u32 = config.get("rewind", "frames_per_snapshot").unwrap_or(4);
+    let rewind_seconds: u32 = config.get("rewind", "seconds").unwrap_or(30);
+    let link_mode: String = config.get("link", "mode").unwrap_or("none".to_string());
+    let link_address: String = config.get("link", "address").unwrap_or("127.0.0.1:8765".to_string());
+    let printer_dir: String = config.get("link", "printer_dir").unwrap_or("printouts".to_string());
+    let record_movie = config.get::<String>("movie", "record").map_or(false, |s| {
+        s.to_lowercase() == "true"
+    });
+    let movie_path: String = config.get("movie", "play").unwrap_or("".to_string());
 
     if let Ok(mut file) = File::create("disassembly.txt") {
         let _ = file.write(Emulator::disassemble_file(&game_path.clone()).as_ref());
     }
 
-	let mut emu = Emulator::new(bios_breakpoint, unimpl_instr_breakpoint, inf_loop_breakpoint);
+    let breakpoints = Breakpoints {
+        bios: bios_breakpoint,
+        unimpl_instr: unimpl_instr_breakpoint,
+        inf_loop: inf_loop_breakpoint
+    };
+
+	let mut emu = Emulator::new();
 	emu.set_controls(controls);
-    emu.load_bios(bios_path);
-    emu.load_game(game_path);
+    if let Err(e) = emu.load_bios(bios_path) {
+        println!("Could not load BIOS ({})", e);
+        emu.skip_bios();
+    }
+    match emu.load_game(game_path.clone()) {
+        Ok(()) => {},
+        Err(e @ LoadError::BadSave(_)) => println!("Running without a save ({})", e),
+        Err(e) => {
+            println!("Could not load \"{}\": {}", game_path, e);
+            return;
+        }
+    }
+    let link = match link_mode.to_lowercase().as_ref() {
+        "host" => {
+            println!("Waiting for another emulator to join on {}", link_address);
+            Some(TcpLink::host(&*link_address))
+        },
+        "join" => Some(TcpLink::join(&*link_address)),
+        "printer" => {
+            println!("Saving printouts to \"{}\"", printer_dir);
+            emu.connect_serial(Box::new(Printer::new(&printer_dir)));
+            None
+        },
+        _ => None
+    };
+    match link {
+        Some(Ok(link)) => {
+            println!("Link cable connected");
+            emu.connect_serial(Box::new(link));
+        },
+        Some(Err(e)) => println!("Could not connect link cable to {} ({})", link_address, e),
+        None => ()
+    }
+
+    let mut player = None;
+    if !movie_path.is_empty() {
+        match File::open(&movie_path).map_err(|e| e.into()).and_then(|mut file| Movie::load(&mut file))
+                                     .and_then(|movie| MoviePlayer::new(movie, &mut emu)) {
+            Ok(movie) => {
+                println!("Playing back \"{}\"", movie_path);
+                state.playing_movie = true;
+                player = Some(movie);
+            },
+            Err(e) => println!("Could not play back \"{}\" ({})", movie_path, e)
+        }
+    }
+    let mut movie = None;
+    if record_movie && player.is_none() {
+        movie = start_movie(&emu, true);
+        state.recording_movie = movie.is_some();
+    }
 
 	let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -80,6 +165,24 @@ fn main() {
                                      .build_glium()
                                      .unwrap();
 
+    let mut audio = if sound_enabled {
+        match sdl_context.audio().and_then(|audio| AudioPlayer::new(&audio)) {
+            Ok(player) => Some(player),
+            Err(e) => {
+                println!("Could not open audio device ({}); running without sound", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut recording: Option<WavWriter<BufWriter<File>>> = None;
+    let mut rewinder = if rewind_enabled {
+        Some(Rewinder::new(frames_per_snapshot, (rewind_seconds*FPS/frames_per_snapshot.max(1)) as usize))
+    } else {
+        None
+    };
     let mut start = PreciseTime::now();
 
     let mut cycles_this_frame = 0;
@@ -103,17 +206,162 @@ fn main() {
         }
         handle_input(&mut event_pump, &mut state, &mut dstate, &mut emu, dev_keys_enabled, only_gb_buttons);
         
-        while (!state.paused || state.adv_frame) && cycles_this_frame < CYCLES_PER_FRAME*emu.get_speed() {
-            cycles_this_frame += emu.step(&mut state, &mut dstate);
-            state.adv_frame = false;
+        let playback = match player {
+            Some(ref mut player) if !state.paused => Some(player.play_frame(&mut emu)),
+            _ => None
+        };
+        match rewinder {
+            // Movies take over emulation until they finish
+            _ if state.playing_movie => match playback {
+                Some(Ok(Some(cycles))) => cycles_this_frame = cycles,
+                Some(Ok(None)) => {
+                    println!("Movie finished");
+                    state.playing_movie = false;
+                },
+                Some(Err(e)) => {
+                    println!("Movie stopped ({})", e);
+                    state.playing_movie = false;
+                },
+                None => ()
+            },
+            // Holding the rewind key goes back a snapshot every frame instead of emulating
+            Some(ref mut rewinder) if state.rewinding && !state.paused => {
+                rewinder.rewind(&mut emu);
+            },
+            _ => {
+                while (!state.paused || state.adv_frame) && cycles_this_frame < CYCLES_PER_FRAME*emu.get_speed() {
+                    if state.debug {
+                        // TODO: always store debug info but do so without slowing everything down
+                        if state.debug_regs {
+                            dstate.add_text(&format!("{:?}\n", emu.get_regs()), 2);
+                        }
+                        dstate.add_text(&format!("{}\n", emu.disassemble_next()), 1);
+                    }
+
+                    let (cycles, event) = emu.step();
+                    if let Some(event) = event {
+                        breakpoints.handle_event(event, &emu, &mut state);
+                    }
+                    cycles_this_frame += cycles;
+                    state.adv_frame = false;
+                }
+                if cycles_this_frame >= CYCLES_PER_FRAME*emu.get_speed() {
+                    if let Some(ref mut rewinder) = rewinder {
+                        rewinder.record(&emu);
+                    }
+                }
+                if cycles_this_frame > 0 {
+                    if let Some((_, ref mut recorder)) = movie {
+                        recorder.record_frame(&emu, cycles_this_frame);
+                    }
+                }
+            }
+        }
+        if !state.playing_movie {
+            player = None;
+        }
+        if state.recording_movie != movie.is_some() {
+            if state.recording_movie {
+                movie = start_movie(&emu, false);
+                state.recording_movie = movie.is_some();
+            } else if let Some((path, recorder)) = movie.take() {
+                finish_movie(&path, recorder);
+            }
         }
+
+        let serial = emu.take_serial_output();
+        if !serial.is_empty() {
+            if print_serial {
+                print!("{}", serial);
+                let _ = std::io::stdout().flush();
+            }
+            dstate.add_serial(&serial);
+        }
+
+        let samples = emu.take_audio_samples();
+        if state.recording != recording.is_some() {
+            if state.recording {
+                recording = start_recording();
+                state.recording = recording.is_some();
+            } else if let Some(wav) = recording.take() {
+                if let Err(e) = wav.finish() {
+                    println!("Could not finish recording ({})", e);
+                }
+            }
+        }
+        if let Some(ref mut wav) = recording {
+            if let Err(e) = wav.write_samples(&samples) {
+                println!("Could not record audio ({})", e);
+            }
+        }
+        let synced = match audio {
+            Some(ref mut audio) => {
+                audio.play(&samples, state.speed);
+                // Let the audio device decide when the next frame should start
+                if sync_to_audio && !state.paused {
+                    audio.wait_for_space();
+                    true
+                } else {
+                    false
+                }
+            },
+            None => false
+        };
+
         if frames_until_render == 0 {
-            renderer.render(&display, emu.get_screen(), &state, &mut dstate);
-            fps.tick();
+            renderer.render(&display, emu.get_screen(), emu.get_apu(), &state, &mut dstate);
+            if !synced {
+                fps.tick();
+            }
         }
 
         frames_until_render = (frames_until_render+1)%state.speed;
         cycles_per_second += cycles_this_frame;
         cycles_this_frame = 0;
     }
+    if let Some(wav) = recording {
+        if let Err(e) = wav.finish() {
+            println!("Could not finish recording ({})", e);
+        }
+    }
+    if let Some((path, recorder)) = movie {
+        finish_movie(&path, recorder);
+    }
+}
+
+fn start_recording() -> Option<WavWriter<BufWriter<File>>> {
+    let path = format!("audio_{}.wav", time::get_time().sec);
+    match File::create(&path).and_then(|file| WavWriter::new(BufWriter::new(file))) {
+        Ok(wav) => {
+            println!("Recording audio to \"{}\"", path);
+            Some(wav)
+        },
+        Err(e) => {
+            println!("Could not record audio to \"{}\" ({})", path, e);
+            None
+        }
+    }
+}
+
+fn start_movie(emu: &Emulator, from_power_on: bool) -> Option<(String, MovieRecorder)> {
+    let path = format!("movie_{}.rgbm", time::get_time().sec);
+    // Make sure the movie can actually be saved before recording anything
+    match File::create(&path) {
+        Ok(_) => {
+            println!("Recording input to \"{}\"", path);
+            Some((path, MovieRecorder::new(emu, from_power_on)))
+        },
+        Err(e) => {
+            println!("Could not record input to \"{}\" ({})", path, e);
+            None
+        }
+    }
+}
+
+fn finish_movie(path: &str, recorder: MovieRecorder) {
+    let frames = recorder.num_frames();
+    match File::create(path).and_then(|file| recorder.finish().save(&mut BufWriter::new(file))) {
+        Ok(_) => println!("Saved {} frames of input to \"{}\"", frames, path),
+        Err(e) => println!("Could not save movie to \"{}\" ({})", path, e)
+    }
 }
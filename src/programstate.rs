@@ -4,6 +4,8 @@
 // Not sure where the most appropriate place for this code is, 
 // so it is separated it into its own file for now
 
+use rgb::emulator::{Emulator, Event};
+
 use utils::*;
 
 #[derive(Debug)]
@@ -14,7 +16,16 @@ pub struct ProgramState {
     pub paused:		bool,
     pub adv_frame:	bool,
     pub debug_regs: bool,
+    pub debug_audio: bool,
+    pub debug_serial: bool,
+    pub recording:  bool,
+    pub rewinding:  bool,
+    pub recording_movie: bool,
+    // Keyboard input is ignored while a movie is being played back
+    pub playing_movie: bool,
     pub speed:		u64,
+    // Path of the game being played (save state slots are stored next to it)
+    pub game_path:  String,
 }
 
 impl ProgramState {
@@ -26,7 +37,14 @@ impl ProgramState {
     		paused: false, 
     		adv_frame: false,
             debug_regs: false,
-    		speed: 1
+            debug_audio: false,
+            debug_serial: false,
+            recording: false,
+            rewinding: false,
+            recording_movie: false,
+            playing_movie: false,
+    		speed: 1,
+            game_path: String::new()
     	}
     }
 }
@@ -36,6 +54,8 @@ pub struct DebugState {
     pub buffer: String,
     pub cursor: usize,
     pub num_lines: usize,
+    // Everything the game has sent over the serial port
+    pub serial: String,
 }
 
 impl DebugState {
@@ -44,6 +64,7 @@ impl DebugState {
             buffer: String::new(),
             cursor: 0,
             num_lines: 0,
+            serial: String::new(),
         }
     }
     // TODO: Make faster. Maybe replace buffer with array and keep track of "top" of buffer
@@ -61,4 +82,51 @@ impl DebugState {
         self.cursor += if self.cursor == self.num_lines {num_lines} else {0};
         self.num_lines += num_lines;
     }
+    pub fn add_serial(&mut self, text: &str) {
+        self.serial += text;
+        if self.serial.len() > MAX_SERIAL_CONSOLE_SIZE {
+            // Drop the oldest text, starting from a new line if there's one nearby
+            let mut start = self.serial.len() - MAX_SERIAL_CONSOLE_SIZE;
+            while !self.serial.is_char_boundary(start) {
+                start += 1;
+            }
+            if let Some(newline) = self.serial[start..].find('\n').filter(|&i| i < NUM_CHARS_PER_LINE as usize) {
+                start += newline + 1;
+            }
+            self.serial = self.serial.split_off(start);
+        }
+    }
+}
+
+#[derive(Debug)]
+// Which emulator events should cause emulation to pause
+pub struct Breakpoints {
+    pub bios:           bool,
+    pub unimpl_instr:   bool,
+    pub inf_loop:       bool,
+}
+
+impl Breakpoints {
+    pub fn handle_event(&self, event: Event, emu: &Emulator, state: &mut ProgramState) {
+        match event {
+            Event::BiosFinished => {
+                if self.bios {
+                    state.paused = true;
+                }
+            },
+            Event::InfiniteLoop(_) => {
+                println!("Error: Emulation caught in infinite loop");
+                if self.inf_loop {
+                    state.paused = true;
+                }
+            },
+            Event::UnimplementedInstruction(address, opcode) => {
+                println!("\nUnimplemented instruction at memory address ({:#X}) [{:#X}]\n{}\n", 
+                    address, opcode, emu.disassemble_at(address));
+                if self.unimpl_instr {
+                    state.paused = true;
+                }
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -2,6 +2,7 @@ use std::fs::File;
 use std::io::prelude::*;
 
 use glium::{Surface, VertexBuffer, IndexBuffer, Program, index, Frame};
+use glium::index::{NoIndices, PrimitiveType};
 use glium::texture::*;
 
 use glium_text;
@@ -9,7 +10,7 @@ use glium_text::{TextSystem, FontTexture, TextDisplay};
 
 use glium_sdl2::SDL2Facade;
 
-use emulator::Color;
+use emulator::{Color, Apu};
 
 use super::{ProgramState, DebugState};
 use super::utils::*;
@@ -22,11 +23,21 @@ struct Vertex {
 
 implement_vertex!(Vertex, pos, uv);
 
+#[derive(Debug, Clone, Copy)]
+struct LineVertex {
+    pos:    [f32; 2]
+}
+
+implement_vertex!(LineVertex, pos);
+
+const CHANNEL_NAMES: [&'static str; 4] = ["Square 1", "Square 2", "Wave", "Noise"];
+
 pub struct Renderer {
 	vert_buffer: VertexBuffer<Vertex>,
 	half_buffer: VertexBuffer<Vertex>,
 	index_buffer: IndexBuffer<u8>,
 	program: Program,
+	line_program: Program,
 	system: TextSystem,
 	font: FontTexture,
 	white: (f32, f32, f32),
@@ -35,15 +46,8 @@ pub struct Renderer {
 
 impl Renderer {
 	pub fn new(display: &SDL2Facade, white: u32, black: u32) -> Renderer {
-		let mut vert_shader_src = String::new();
-		let mut frag_shader_src = String::new();
-
-		let mut vert_file = File::open("shaders/simp.vert").unwrap();
-		let mut frag_file = File::open("shaders/simp.frag").unwrap();
-		let _ = vert_file.read_to_string(&mut vert_shader_src);
-		let _ = frag_file.read_to_string(&mut frag_shader_src);
-
-		let program = Program::from_source(display, &vert_shader_src, &frag_shader_src, None).unwrap();
+		let program = Renderer::load_program(display, "shaders/simp.vert", "shaders/simp.frag");
+		let line_program = Renderer::load_program(display, "shaders/line.vert", "shaders/line.frag");
 
 		// normally fill screen with emulator
 		let v1 = Vertex{pos: [-1.0,  1.0], uv: [0.0, 0.0]};
@@ -80,12 +84,24 @@ impl Renderer {
 			half_buffer: half_buffer, 
 			index_buffer: index_buffer, 
 			program: program,
+			line_program: line_program,
 			system: system,
 			font: font,
 			white: white, 
 			black: black
 		}
 	}
+	fn load_program(display: &SDL2Facade, vert_path: &str, frag_path: &str) -> Program {
+		let mut vert_shader_src = String::new();
+		let mut frag_shader_src = String::new();
+
+		let mut vert_file = File::open(vert_path).unwrap();
+		let mut frag_file = File::open(frag_path).unwrap();
+		let _ = vert_file.read_to_string(&mut vert_shader_src);
+		let _ = frag_file.read_to_string(&mut frag_shader_src);
+
+		Program::from_source(display, &vert_shader_src, &frag_shader_src, None).unwrap()
+	}
 	fn make_texture(&self, display: &SDL2Facade, screen: &[[Color; 160]; 144]) -> Texture2d {
 	    let raw = screen.into_iter()
 	    				.flat_map(|row| {
@@ -130,25 +146,77 @@ impl Renderer {
 		target.draw(buf, &self.index_buffer, &self.program, &uniform!{sample: &texture}, 
 					&Default::default()).unwrap();
 	}
-	fn display_debug_info(&self, target: &mut Frame, dstate: &DebugState) {
-		let cursor = if dstate.num_lines - dstate.cursor < NUM_LINES_ON_SCREEN {
+	fn display_debug_info(&self, display: &SDL2Facade, target: &mut Frame, apu: &Apu, state: &ProgramState, dstate: &DebugState) {
+		// The sound channels' oscilloscopes take up the bottom of the panel
+		let num_lines = if state.debug_audio {NUM_LINES_ON_SCREEN - 4*SCOPE_LINES} else {NUM_LINES_ON_SCREEN};
+		let cursor = if dstate.num_lines - dstate.cursor < num_lines {
 			// usizes are unsigned so this subtraction is just wrong (same in input.rs). Oh well...
-			max(0, dstate.num_lines - NUM_LINES_ON_SCREEN)
+			max(0, dstate.num_lines - num_lines)
 		} else {
 			dstate.cursor
 		};
 
-		for (i, line) in dstate.buffer.lines().skip(cursor).take(NUM_LINES_ON_SCREEN).enumerate() {
+		if state.debug_serial {
+			self.display_serial_console(target, dstate, num_lines);
+		} else {
+			for (i, line) in dstate.buffer.lines().skip(cursor).take(num_lines).enumerate() {
+				self.render_line_of_text(1.0 - (i as f32)*LINE_HEIGHT, line, target);
+			}
+		}
+		if state.debug_audio {
+			for channel in 0..4 {
+				let top = 1.0 - ((num_lines + channel*SCOPE_LINES) as f32)*LINE_HEIGHT;
+				self.display_channel_scope(display, target, apu, channel, top);
+			}
+		}
+	}
+	// Shows the last few lines the game sent over the serial port, wrapped to fit in the panel
+	fn display_serial_console(&self, target: &mut Frame, dstate: &DebugState, num_lines: usize) {
+		let mut lines = vec!["===== SERIAL OUTPUT =====".to_string()];
+		for line in dstate.serial.lines() {
+			let chars = line.chars().filter(|c| !c.is_control()).collect::<Vec<_>>();
+			if chars.is_empty() {
+				lines.push(String::new());
+			}
+			for chunk in chars.chunks(NUM_CHARS_PER_LINE as usize) {
+				lines.push(chunk.iter().cloned().collect());
+			}
+		}
+
+		let skip = if lines.len() > num_lines {lines.len() - num_lines} else {0};
+		for (i, line) in lines.iter().skip(skip).enumerate() {
 			self.render_line_of_text(1.0 - (i as f32)*LINE_HEIGHT, line, target);
 		}
 	}
+	fn display_channel_scope(&self, display: &SDL2Facade, target: &mut Frame, apu: &Apu, channel: usize, top: f32) {
+		let scope = apu.channel_scope(channel);
+		let level = scope.iter().fold(0f32, |acc, &s| acc.max(s.abs()));
+		let muted = apu.is_channel_muted(channel);
+		let label = format!("CH{} {:<8} level {:>3}%{}", channel + 1, CHANNEL_NAMES[channel],
+							(level*100.0) as u32, if muted {" MUTED"} else {""});
+		self.render_line_of_text(top, &label, target);
+
+		// The waveform fills the rest of the channel's lines, with 0 in the middle
+		let (start, width) = (1.0 - 2.0 * PORTION_DEBUG, 2.0 * PORTION_DEBUG);
+		let half_height = (SCOPE_LINES - 1) as f32*LINE_HEIGHT/2.0;
+		let middle = top - LINE_HEIGHT - half_height;
+		let vertices = scope.iter().enumerate().map(|(i, &s)| {
+			LineVertex{pos: [start + width*i as f32/(scope.len() - 1) as f32, middle + 0.9*half_height*s]}
+		}).collect::<Vec<_>>();
+		let buffer = VertexBuffer::new(display, &vertices).unwrap();
+
+		// TODO: Make scope colors customizable
+		let color = if muted {[0.4f32, 0.4, 0.4]} else {[0.2f32, 1.0, 0.2]};
+		target.draw(&buffer, &NoIndices(PrimitiveType::LineStrip), &self.line_program,
+					&uniform!{color: color}, &Default::default()).unwrap();
+	}
 
-	pub fn render(&self, display: &SDL2Facade, screen: &[[Color; 160]; 144], state: &ProgramState, dstate: &DebugState) {
+	pub fn render(&self, display: &SDL2Facade, screen: &[[Color; 160]; 144], apu: &Apu, state: &ProgramState, dstate: &DebugState) {
 		let mut target = display.draw();
 		target.clear(None, Some((0.0, 0.0, 0.0, 1.0)), false, None, None);
 
 		if state.debug {
-			self.display_debug_info(&mut target, dstate);
+			self.display_debug_info(display, &mut target, apu, state, dstate);
 		}
 		self.display_gameboy(display, &mut target, screen, state);
 
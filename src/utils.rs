@@ -11,8 +11,12 @@ pub const LINE_HEIGHT: f32 = 2.0/NUM_LINES_ON_SCREEN as f32;
 pub const TEXT_HEIGHT: f32 = 0.618 * LINE_HEIGHT; // 1/golden ratio for aesthetic reasons
 pub const NUM_CHARS_PER_LINE: u32 = 40;
 pub const CHAR_WIDTH: f32 = 2.0*PORTION_DEBUG/NUM_CHARS_PER_LINE as f32;
+// Lines of the debug panel given to each sound channel's oscilloscope (including its label)
+pub const SCOPE_LINES: usize = 3;
 
 pub const MAX_DEBUG_BUFFER_SIZE: usize = 1000;
+// The serial console only keeps (roughly) this many bytes of the most recent output
+pub const MAX_SERIAL_CONSOLE_SIZE: usize = 4096;
 
 pub fn prompt_for_val(prompt: &str) -> String {
     print!("{}", prompt);
@@ -30,6 +34,15 @@ pub fn string_to_u16(s: &str) -> Result<u16, <u16 as FromStr>::Err> {
 	u16::from_str_radix(if base == 10 {s} else {&s[2..]}, base)
 }
 
+// Save states go next to the game, e.g. games/Tetris.gb has its slot 1 in games/Tetris.state1
+pub fn save_state_path(game: &str, slot: u8) -> String {
+	let stem = match game.rfind('.') {
+		Some(dot_pos) => &game[..dot_pos],
+		None		  => game
+	};
+	format!("{}.state{}", stem, slot)
+}
+
 pub fn max(lhs: usize, rhs: usize) -> usize {
 	if rhs > lhs {rhs} else {lhs}
 }
@@ -0,0 +1,75 @@
+// Runs every test ROM in test_roms/ (or the directory in RGB_TEST_ROMS) and prints a report
+//
+// The ROMs aren't included in the repository, so nothing runs unless they have been put there.
+// Any ROM listed (by its path relative to that directory) in passing.txt must pass, so the
+// list doubles as a record of which tests should keep working.
+//
+// Run with `cargo test --release --no-default-features --test test_roms -- --nocapture`
+extern crate rgb;
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use rgb::emulator::Emulator;
+use rgb::emulator::testrom::*;
+
+// Long enough for Blargg's cpu_instrs, the slowest of them
+const MAX_FRAMES: u64 = 60*120;
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+	let mut entries = match fs::read_dir(dir) {
+		Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect::<Vec<_>>(),
+		Err(_) => return
+	};
+	entries.sort();
+	for path in entries {
+		if path.is_dir() {
+			find_roms(&path, roms);
+		} else if path.extension().map_or(false, |ext| ext == "gb" || ext == "gbc") {
+			roms.push(path);
+		}
+	}
+}
+
+#[test]
+fn test_roms() {
+	let dir = PathBuf::from(env::var("RGB_TEST_ROMS").unwrap_or("test_roms".to_string()));
+	let mut roms = Vec::new();
+	find_roms(&dir, &mut roms);
+	if roms.is_empty() {
+		println!("No test ROMs found in \"{}\"; skipping", dir.display());
+		return;
+	}
+
+	let mut results = Vec::new();
+	for rom in &roms {
+		let name = rom.strip_prefix(&dir).unwrap_or(rom).to_string_lossy().replace('\\', "/");
+		let suite = Suite::from_path(&name);
+		let mut emu = Emulator::new();
+		emu.skip_bios();
+		results.push(match emu.load_game(rom.to_string_lossy().into_owned()) {
+			Ok(_) => run_test_rom(&mut emu, &name, suite, MAX_FRAMES),
+			Err(e) => TestResult {
+				name: name,
+				suite: suite,
+				outcome: Outcome::Failed(format!("could not load: {}", e)),
+				frames: 0
+			}
+		});
+	}
+	let report = report(&results);
+	println!("{}", report);
+	if let Ok(mut file) = File::create("target/test_rom_report.md") {
+		let _ = file.write_all(report.as_bytes());
+	}
+
+	let mut expected = String::new();
+	let _ = File::open(dir.join("passing.txt")).and_then(|mut file| file.read_to_string(&mut expected));
+	let regressions = expected.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.filter(|&name| !results.iter().any(|result| result.name == name && result.outcome == Outcome::Passed))
+		.collect::<Vec<_>>();
+	assert!(regressions.is_empty(), "These tests should pass but didn't: {:?}", regressions);
+}